@@ -1,12 +1,21 @@
 use crate::{CoreSettings, DialogType};
-use slint::SharedString;
+use libcoresettings::session::AdminSession;
+use qrcode::{Color, QrCode};
+use slint::{ComponentHandle, Image, Rgb8Pixel, SharedPixelBuffer, SharedString};
+use std::sync::{Arc, Mutex};
 
 use log::{error, info};
 
+pub mod audit;
+pub mod groups;
+pub mod restrictions;
 pub mod users;
 
 pub const TOAST_DURATION_MILLIS: i32 = 5000;
 pub const TOAST_GC_DELAY: i32 = 100;
+// Size of a QR code module in pixels, and of the blank border around the code in modules
+const QR_CODE_MODULE_SIZE: usize = 8;
+const QR_CODE_QUIET_ZONE: usize = 4;
 
 pub fn toast_timer_loop_check(gui: &CoreSettings) {
     if gui.get_dialog() == DialogType::Toast {
@@ -23,6 +32,18 @@ pub fn toast_timer_loop_check(gui: &CoreSettings) {
     }
 }
 
+/// Also locks the session once the window is hidden. Under the KMS backend Core Settings owns the whole
+/// display and no other window can take focus, so hiding is the only way for it to lose the user's attention.
+pub fn admin_session_timer_check(gui: &CoreSettings, session: &Arc<Mutex<AdminSession>>) {
+    if !gui.window().is_visible() {
+        session.lock().unwrap().lock();
+    }
+    if !gui.get_admin_lock_set() && !session.lock().unwrap().is_unlocked() {
+        gui.set_admin_lock_set(true);
+        toast(&gui, "Administrator session locked");
+    }
+}
+
 fn toast(gui: &CoreSettings, message: &str) {
     gui.set_sticky_toast(false);
     gui.set_dialog_message(SharedString::from(message));
@@ -36,3 +57,62 @@ pub fn error_toast(gui: &CoreSettings, message: &str, e: anyhow::Error) {
     gui.set_dialog(DialogType::Toast);
     error!("{}: {}", &message, e);
 }
+
+/// Privileged actions are checked against the administrator session here, not only hidden in the UI
+pub fn authorize(gui: &CoreSettings, session: &Arc<Mutex<AdminSession>>) -> bool {
+    if session.lock().unwrap().touch() {
+        return true;
+    }
+
+    gui.set_admin_lock_set(true);
+    toast(&gui, "Administrator login required");
+
+    false
+}
+
+/// Creating the very first user does not require any login: there is no administrator to log in as yet
+pub fn authorize_user_creation(gui: &CoreSettings, session: &Arc<Mutex<AdminSession>>) -> bool {
+    if let Ok(0) = libcoresettings::users::count_admin_users() {
+        return true;
+    }
+
+    authorize(&gui, &session)
+}
+
+/// Who to attribute a privileged action to in the audit log
+pub fn actor(session: &Arc<Mutex<AdminSession>>) -> String {
+    session
+        .lock()
+        .unwrap()
+        .user()
+        .map(str::to_string)
+        .unwrap_or_else(|| "setup".to_string())
+}
+
+/// Renders data as a black-and-white QR code, large enough to be photographed off an e-ink screen
+pub fn qr_code_image(data: &str) -> anyhow::Result<Image> {
+    let code = QrCode::new(data.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to generate QR code: {}", e))?;
+    let modules = code.to_colors();
+    let width = code.width();
+    let size = (width + 2 * QR_CODE_QUIET_ZONE) * QR_CODE_MODULE_SIZE;
+
+    let mut buffer = SharedPixelBuffer::<Rgb8Pixel>::new(size as u32, size as u32);
+    for (i, pixel) in buffer.make_mut_slice().iter_mut().enumerate() {
+        let x = (i % size) / QR_CODE_MODULE_SIZE;
+        let y = (i / size) / QR_CODE_MODULE_SIZE;
+        let dark = x >= QR_CODE_QUIET_ZONE
+            && y >= QR_CODE_QUIET_ZONE
+            && x < width + QR_CODE_QUIET_ZONE
+            && y < width + QR_CODE_QUIET_ZONE
+            && modules[(y - QR_CODE_QUIET_ZONE) * width + x - QR_CODE_QUIET_ZONE] == Color::Dark;
+        let value = if dark { 0 } else { 255 };
+        *pixel = Rgb8Pixel {
+            r: value,
+            g: value,
+            b: value,
+        };
+    }
+
+    Ok(Image::from_rgb8(buffer))
+}
@@ -0,0 +1,55 @@
+use libcoresettings::{audit, removable, session::AdminSession, time};
+use slint::{ModelRc, SharedString, Timer, TimerMode, VecModel, Weak};
+use std::{
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use crate::{AuditEntry, CoreSettings};
+
+use crate::gui_fn::{authorize, error_toast, toast};
+
+pub fn get_audit_log(gui: &CoreSettings, filter: SharedString, session: &Arc<Mutex<AdminSession>>) {
+    if !authorize(&gui, &session) {
+        gui.set_audit_entries(ModelRc::new(VecModel::from(Vec::new())));
+        return;
+    }
+
+    match audit::read() {
+        Ok(entries) => {
+            let entries: Vec<AuditEntry> = entries
+                .iter()
+                .rev()
+                .filter(|entry| audit::matches(&entry, &filter))
+                .map(|entry| AuditEntry {
+                    timestamp: SharedString::from(time::format_timestamp(entry.timestamp)),
+                    actor: SharedString::from(&entry.actor),
+                    action: SharedString::from(entry.action.as_str()),
+                    target: SharedString::from(&entry.target),
+                    success: entry.success,
+                })
+                .collect();
+            gui.set_audit_entries(ModelRc::new(VecModel::from(entries)));
+        }
+        Err(e) => error_toast(&gui, "Failed to read audit log", e.into()),
+    }
+}
+
+pub fn export(gui_weak: Weak<CoreSettings>, timer: &Rc<Timer>, session: Arc<Mutex<AdminSession>>) {
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                if !authorize(&gui, &session) {
+                    return;
+                }
+
+                match removable::first_mountpoint().and_then(|dir| audit::export(&dir)) {
+                    Ok(path) => toast(&gui, &format!("Audit log exported to {}", &path)),
+                    Err(e) => error_toast(&gui, "Failed to export audit log", e.into()),
+                }
+            }
+        },
+    )
+}
@@ -0,0 +1,279 @@
+use std::{
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use libcoresettings::{
+    audit::{self, Action},
+    config::{self, Config},
+    groups::{self, Group},
+    privilege,
+    session::AdminSession,
+};
+use libqinit::storage_encryption;
+use log::error;
+
+use crate::gui_fn::{actor, authorize, error_toast, toast};
+use crate::{CoreSettings, GroupMember, PrivilegeEscalation, SettingsPage, SystemGroup};
+use slint::{SharedString, Timer, TimerMode, Weak};
+
+const FAILED_MEMBERSHIP_TOGGLE: &str = "Failed to change group membership";
+
+fn to_system_group(group: &Group, config: &Config) -> SystemGroup {
+    SystemGroup {
+        name: SharedString::from(&group.name),
+        gid: group.gid as i32,
+        protected: groups::is_protected(&group).unwrap_or(true),
+        admin: config.admin_groups.contains(&group.name),
+    }
+}
+
+pub fn get_groups(gui: &CoreSettings) {
+    let config = match Config::read() {
+        Ok(config) => config,
+        Err(e) => {
+            error_toast(&gui, "Failed to read configuration", e.into());
+            Config::default()
+        }
+    };
+    gui.set_privilege_escalation(match config.privilege_escalation {
+        config::PrivilegeEscalation::None => PrivilegeEscalation::None,
+        config::PrivilegeEscalation::Sudo => PrivilegeEscalation::Sudo,
+        config::PrivilegeEscalation::Doas => PrivilegeEscalation::Doas,
+    });
+
+    match groups::list() {
+        Ok(groups_list) => {
+            let groups_vec: Vec<SystemGroup> = groups_list
+                .iter()
+                .map(|group| to_system_group(&group, &config))
+                .collect();
+            gui.set_groups(slint::ModelRc::new(slint::VecModel::from(groups_vec)));
+        }
+        Err(e) => {
+            error_toast(&gui, "Failed to get groups list", e.into());
+            gui.set_settings_page(SettingsPage::None);
+        }
+    }
+}
+
+pub fn get_group_details(
+    gui: &CoreSettings,
+    group: SharedString,
+    session: &Arc<Mutex<AdminSession>>,
+) {
+    session.lock().unwrap().touch();
+    update_group_details(&gui, group);
+}
+
+fn update_group_details(gui: &CoreSettings, group: SharedString) {
+    let group = match groups::get(&group) {
+        Ok(group) => group,
+        Err(e) => {
+            error_toast(&gui, "Failed to get group's details", e.into());
+            return;
+        }
+    };
+
+    gui.set_selected_group(to_system_group(&group, &Config::read().unwrap_or_default()));
+
+    // Show every user known to Core Settings, plus members which may have been added by other means
+    let members = groups::members(&group).unwrap_or_else(|e| {
+        error!("Failed to get members of group '{}': {}", &group.name, e);
+        group.members.clone()
+    });
+    let mut users = storage_encryption::get_users_using_storage_encryption().unwrap_or_default();
+    for member in &members {
+        if !users.contains(&member) {
+            users.push(member.clone());
+        }
+    }
+
+    let members_vec: Vec<GroupMember> = users
+        .iter()
+        .map(|user| GroupMember {
+            name: SharedString::from(user),
+            member: members.contains(&user),
+        })
+        .collect();
+    gui.set_selected_group_members(slint::ModelRc::new(slint::VecModel::from(members_vec)));
+}
+
+pub fn create(
+    gui_weak: Weak<CoreSettings>,
+    name: SharedString,
+    timer: &Rc<Timer>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let gui_weak = gui_weak.clone();
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        {
+            move || {
+                if let Some(gui) = gui_weak.upgrade() {
+                    if !authorize(&gui, &session) {
+                        return;
+                    }
+
+                    if let Err(e) = groups::create(&name) {
+                        audit::record(&actor(&session), Action::CreateGroup, &name, false);
+                        error_toast(&gui, "Failed to create group", e.into());
+                    } else {
+                        audit::record(&actor(&session), Action::CreateGroup, &name, true);
+                        toast(&gui, "Group created successfully");
+                    }
+                    refresh_groups_ui(&gui);
+                }
+            }
+        },
+    );
+}
+
+pub fn delete(
+    gui_weak: Weak<CoreSettings>,
+    name: &str,
+    timer: &Rc<Timer>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let gui_weak = gui_weak.clone();
+    let name = name.to_owned();
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        {
+            move || {
+                if let Some(gui) = gui_weak.upgrade() {
+                    if !authorize(&gui, &session) {
+                        return;
+                    }
+
+                    if let Err(e) = groups::delete(&name) {
+                        audit::record(&actor(&session), Action::DeleteGroup, &name, false);
+                        error_toast(&gui, "Failed to delete group", e.into());
+                    } else {
+                        audit::record(&actor(&session), Action::DeleteGroup, &name, true);
+                        toast(&gui, "Group deleted successfully");
+                    }
+                    gui.set_selected_group(SystemGroup {
+                        name: SharedString::new(),
+                        gid: 0,
+                        protected: false,
+                        admin: false,
+                    });
+                    refresh_groups_ui(&gui);
+                }
+            }
+        },
+    );
+}
+
+pub fn set_membership(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    group: &str,
+    member: bool,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let admin_group = Config::read()
+            .map(|config| config.admin_groups.iter().any(|entry| entry == group))
+            .unwrap_or(true);
+        if !member && admin_group && libcoresettings::users::count_admin_users().unwrap_or(0) < 2 {
+            toast(&gui, "At least one administrator required");
+        } else {
+            let action = if member {
+                Action::AddGroupMember
+            } else {
+                Action::RemoveGroupMember
+            };
+            let target = format!("{} ({})", &user, &group);
+            if let Err(e) = groups::set_membership(&user, &group, member) {
+                audit::record(&actor(&session), action, &target, false);
+                error_toast(&gui, &FAILED_MEMBERSHIP_TOGGLE, e.into());
+            } else {
+                audit::record(&actor(&session), action, &target, true);
+            }
+        }
+        refresh_groups_ui(&gui);
+    }
+}
+
+pub fn set_admin_group(
+    gui_weak: Weak<CoreSettings>,
+    group: &str,
+    admin: bool,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let action = if admin {
+            Action::SetAdminGroup
+        } else {
+            Action::UnsetAdminGroup
+        };
+        if let Err(e) = privilege::set_admin_group(&group, admin) {
+            audit::record(&actor(&session), action, &group, false);
+            error_toast(&gui, "Failed to change administrator group", e.into());
+        } else {
+            audit::record(&actor(&session), action, &group, true);
+        }
+        refresh_groups_ui(&gui);
+    }
+}
+
+pub fn set_privilege_escalation(
+    gui_weak: Weak<CoreSettings>,
+    privilege_escalation: PrivilegeEscalation,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let privilege_escalation = match privilege_escalation {
+            PrivilegeEscalation::None => config::PrivilegeEscalation::None,
+            PrivilegeEscalation::Sudo => config::PrivilegeEscalation::Sudo,
+            PrivilegeEscalation::Doas => config::PrivilegeEscalation::Doas,
+        };
+        let target = format!("{:?}", &privilege_escalation).to_lowercase();
+        if let Err(e) = privilege::set_privilege_escalation(privilege_escalation) {
+            audit::record(
+                &actor(&session),
+                Action::SetPrivilegeEscalation,
+                &target,
+                false,
+            );
+            error_toast(
+                &gui,
+                "Failed to change privilege escalation policy",
+                e.into(),
+            );
+        } else {
+            audit::record(
+                &actor(&session),
+                Action::SetPrivilegeEscalation,
+                &target,
+                true,
+            );
+        }
+        refresh_groups_ui(&gui);
+    }
+}
+
+fn refresh_groups_ui(gui: &CoreSettings) {
+    get_groups(&gui);
+
+    let selected_group = gui.get_selected_group();
+    if !selected_group.name.is_empty() {
+        update_group_details(&gui, selected_group.name);
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex};
+
+use libcoresettings::{
+    audit::{self, Action},
+    restrictions::{self, Restrictions},
+    session::AdminSession,
+    users::is_admin,
+};
+
+use crate::gui_fn::{actor, authorize, error_toast};
+use crate::{CoreSettings, UserRestrictions};
+use slint::{ModelRc, SharedString, VecModel, Weak};
+
+pub fn get_restrictions(
+    gui: &CoreSettings,
+    user: SharedString,
+    session: &Arc<Mutex<AdminSession>>,
+) {
+    session.lock().unwrap().touch();
+    gui.set_restrictions_user(user.clone());
+    update_restrictions(&gui, &user);
+}
+
+fn update_restrictions(gui: &CoreSettings, user: &str) {
+    let restrictions = match restrictions::get(&user) {
+        Ok(restrictions) => restrictions,
+        Err(e) => {
+            error_toast(&gui, "Failed to read restrictions", e.into());
+            None
+        }
+    };
+
+    let (start_hour, end_hour) = restrictions
+        .as_ref()
+        .and_then(|restrictions| restrictions.allowed_hours)
+        .unwrap_or((0, 0));
+    let allowed_apps: Vec<SharedString> = restrictions
+        .as_ref()
+        .map(|restrictions| {
+            restrictions
+                .allowed_apps
+                .iter()
+                .map(|app| SharedString::from(app))
+                .collect()
+        })
+        .unwrap_or_default();
+    gui.set_user_restrictions(UserRestrictions {
+        admin: is_admin(&user),
+        restricted: restrictions.is_some(),
+        allowed_apps: ModelRc::new(VecModel::from(allowed_apps)),
+        daily_limit_minutes: restrictions
+            .as_ref()
+            .and_then(|restrictions| restrictions.daily_limit_minutes)
+            .unwrap_or(0) as i32,
+        start_hour: start_hour as i32,
+        end_hour: end_hour as i32,
+    });
+}
+
+fn apply(
+    gui: &CoreSettings,
+    user: &str,
+    restrictions: Option<Restrictions>,
+    session: &Arc<Mutex<AdminSession>>,
+) {
+    let (action, result) = match restrictions {
+        Some(restrictions) => (
+            Action::SetRestrictions,
+            restrictions::set(&user, &restrictions),
+        ),
+        None => (Action::RemoveRestrictions, restrictions::remove(&user)),
+    };
+    if let Err(e) = result {
+        audit::record(&actor(&session), action, &user, false);
+        error_toast(&gui, "Failed to change restrictions", e.into());
+    } else {
+        audit::record(&actor(&session), action, &user, true);
+    }
+    update_restrictions(&gui, &user);
+}
+
+/// Keeps the allowed applications as they are. Hours of use are not limited if `start_hour` and `end_hour`
+/// are the same.
+pub fn set_restrictions(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    restricted: bool,
+    daily_limit_minutes: i32,
+    start_hour: i32,
+    end_hour: i32,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let restrictions = if restricted {
+            let allowed_apps = match restrictions::get(&user) {
+                Ok(restrictions) => restrictions
+                    .map(|restrictions| restrictions.allowed_apps)
+                    .unwrap_or_default(),
+                Err(e) => {
+                    error_toast(&gui, "Failed to read restrictions", e.into());
+                    return;
+                }
+            };
+            Some(Restrictions {
+                allowed_apps,
+                daily_limit_minutes: (daily_limit_minutes > 0)
+                    .then_some(daily_limit_minutes as u32),
+                allowed_hours: (start_hour != end_hour)
+                    .then_some((start_hour as u8, end_hour as u8)),
+            })
+        } else {
+            None
+        };
+        apply(&gui, &user, restrictions, &session);
+    }
+}
+
+pub fn set_allowed_app(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    app: &str,
+    allowed: bool,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let mut restrictions = match restrictions::get(&user) {
+            Ok(Some(restrictions)) => restrictions,
+            Ok(None) => return,
+            Err(e) => {
+                error_toast(&gui, "Failed to read restrictions", e.into());
+                return;
+            }
+        };
+        let app = app.trim().to_string();
+        restrictions
+            .allowed_apps
+            .retain(|allowed_app| allowed_app != &app);
+        if allowed {
+            restrictions.allowed_apps.push(app);
+        }
+        apply(&gui, &user, Some(restrictions), &session);
+    }
+}
@@ -3,28 +3,71 @@ use std::{
     sync::{Arc, Mutex, mpsc::Sender},
 };
 
-use libcoresettings::users::{AdminLoginStatus, is_admin};
+use libcoresettings::{
+    activity::{self, InUse},
+    audit::{self, Action},
+    autologin, backup, escrow,
+    fsck::{self, Report},
+    fsutil::format_size,
+    lockout::{self, LockedOut},
+    logins, loginscreen, password_history,
+    pin::{self, PinStatus},
+    profiles, rekey, removable,
+    session::AdminSession,
+    time, usage,
+    users::{AdminLoginStatus, is_admin},
+};
 use libqinit::{
     boot_config::BootConfig,
     storage_encryption::{self, DISABLED_MODE_PASSWORD},
 };
 
-use crate::gui_fn::{error_toast, toast};
-use crate::{CoreSettings, SettingsPage, SystemUser};
-use slint::{SharedString, Timer, TimerMode, Weak};
+use crate::gui_fn::{actor, authorize, authorize_user_creation, error_toast, qr_code_image, toast};
+use crate::{
+    BackupEntry, CoreSettings, DialogType, EncryptionDetails, LoginHistory, LoginScreenUser,
+    SettingsPage, StorageUsage, SystemUser, UserProfile,
+};
+use log::error;
+use slint::{
+    ComponentHandle, Image, Model, ModelRc, SharedString, Timer, TimerMode, VecModel, Weak,
+};
 
 const FAILED_ADMIN_STATUS_TOGGLE: &str = "Failed to change administrator status";
 
 pub fn get_users(gui: &CoreSettings, boot_config: Arc<Mutex<BootConfig>>) {
     match storage_encryption::get_users_using_storage_encryption() {
-        Ok(users_using_storage_encryption) => {
+        Ok(mut users_using_storage_encryption) => {
+            // Listed in the same order as on the login screen
+            loginscreen::prune(&users_using_storage_encryption, boot_config.clone());
+            loginscreen::sort(&mut users_using_storage_encryption, boot_config.clone());
             let users_shared_string_vec: Vec<SharedString> = users_using_storage_encryption
                 .iter()
                 .map(|user| SharedString::from(user))
                 .collect();
+            let login_screen_users: Vec<LoginScreenUser> = users_using_storage_encryption
+                .iter()
+                .map(|user| LoginScreenUser {
+                    hidden: loginscreen::is_hidden(&user, boot_config.clone()),
+                    display_name: SharedString::from(
+                        loginscreen::display_name(&user, boot_config.clone()).unwrap_or_default(),
+                    ),
+                })
+                .collect();
             gui.set_users(slint::ModelRc::new(slint::VecModel::from(
                 users_shared_string_vec,
             )));
+            gui.set_login_screen_users(ModelRc::new(VecModel::from(login_screen_users)));
+
+            let stale_users =
+                logins::stale_users(&users_using_storage_encryption).unwrap_or_else(|e| {
+                    error!("Failed to find stale accounts: {}", e);
+                    Vec::new()
+                });
+            let stale: Vec<bool> = users_using_storage_encryption
+                .iter()
+                .map(|user| stale_users.contains(&user))
+                .collect();
+            gui.set_stale_users(ModelRc::new(VecModel::from(stale)));
         }
         Err(e) => {
             error_toast(&gui, "Failed to get users list", e.into());
@@ -37,9 +80,24 @@ pub fn get_users(gui: &CoreSettings, boot_config: Arc<Mutex<BootConfig>>) {
     } else {
         gui.set_default_user(SharedString::from(String::new()))
     }
+    gui.set_auto_login(boot_config.lock().unwrap().system.auto_login);
+}
+
+pub fn get_user_details(
+    gui: &CoreSettings,
+    user: SharedString,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: &Arc<Mutex<AdminSession>>,
+) {
+    session.lock().unwrap().touch();
+    update_user_details(&gui, user, boot_config);
 }
 
-pub fn get_user_details(gui: &CoreSettings, user: SharedString) {
+fn update_user_details(
+    gui: &CoreSettings,
+    user: SharedString,
+    boot_config: Arc<Mutex<BootConfig>>,
+) {
     match storage_encryption::get_encryption_user_details(&user) {
         Ok(details) => gui.set_selected_user(SystemUser {
             encryption: details.encryption_enabled,
@@ -47,6 +105,13 @@ pub fn get_user_details(gui: &CoreSettings, user: SharedString) {
             encrypted_key: SharedString::from(&details.encrypted_key),
             salt: SharedString::from(&details.salt),
             admin: is_admin(&user.clone().to_string()),
+            escrowed: escrow::is_escrowed(&user),
+            pin: pin::is_set(&user),
+            hidden: loginscreen::is_hidden(&user, boot_config.clone()),
+            display_name: SharedString::from(
+                loginscreen::display_name(&user, boot_config.clone()).unwrap_or_default(),
+            ),
+            password_changed: SharedString::from(password_changed(&user)),
         }),
         Err(e) => {
             gui.set_selected_user(SystemUser {
@@ -56,10 +121,196 @@ pub fn get_user_details(gui: &CoreSettings, user: SharedString) {
                 encrypted_key: SharedString::new(),
                 salt: SharedString::new(),
                 admin: is_admin(&user.clone().to_string()),
+                escrowed: escrow::is_escrowed(&user),
+                pin: pin::is_set(&user),
+                hidden: loginscreen::is_hidden(&user, boot_config.clone()),
+                display_name: SharedString::from(
+                    loginscreen::display_name(&user, boot_config.clone()).unwrap_or_default(),
+                ),
+                password_changed: SharedString::from(password_changed(&user)),
             });
             error_toast(&gui, "Failed to get user's details", e.into())
         }
     }
+    update_encryption_details(&gui, &user);
+    update_storage_usage(&gui, &user);
+    update_login_history(&gui, &user);
+}
+
+const RECENT_SESSIONS: usize = 5;
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// How long ago the user's password was changed, or an empty string if unknown
+fn password_changed(user: &str) -> String {
+    match password_history::last_changed(&user) {
+        Ok(Some(changed)) => match time::now().saturating_sub(changed) / SECONDS_PER_DAY {
+            0 => String::from("today"),
+            1 => String::from("yesterday"),
+            days => format!("{} days ago", days),
+        },
+        Ok(None) => String::new(),
+        Err(e) => {
+            error!("Failed to get password age of user '{}': {}", &user, e);
+            String::new()
+        }
+    }
+}
+
+fn update_login_history(gui: &CoreSettings, user: &str) {
+    let sessions = match logins::history(&user) {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            error_toast(&gui, "Failed to read login history", e.into());
+            Vec::new()
+        }
+    };
+
+    let recent_sessions: Vec<SharedString> = sessions
+        .iter()
+        .take(RECENT_SESSIONS)
+        .map(|session| {
+            SharedString::from(format!(
+                "{} to {} ({})",
+                time::format_timestamp(session.start),
+                session
+                    .end
+                    .map(time::format_timestamp)
+                    .unwrap_or(String::from("unknown")),
+                &session.line
+            ))
+        })
+        .collect();
+    gui.set_login_history(LoginHistory {
+        last_login: SharedString::from(
+            sessions
+                .first()
+                .map(|session| time::format_timestamp(session.start))
+                .unwrap_or_default(),
+        ),
+        sessions: ModelRc::new(VecModel::from(recent_sessions)),
+    });
+}
+
+const BYTES_PER_GIB: u64 = 1024 * 1024 * 1024;
+
+fn update_storage_usage(gui: &CoreSettings, user: &str) {
+    gui.set_storage_usage(StorageUsage::default());
+    let gui_weak = gui.as_weak();
+    let user = user.to_string();
+    std::thread::spawn(move || {
+        let result = usage::user_usage(&user)
+            .and_then(|user_usage| Ok((user_usage, usage::partition_usage()?)));
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+            // The selection may have changed in the meantime
+            if gui.get_selected_user().name != user {
+                return;
+            }
+
+            match result {
+                Ok((user_usage, partition_usage)) => {
+                    let limit = user_usage
+                        .quota_bytes
+                        .unwrap_or(partition_usage.total_bytes)
+                        .max(1);
+                    gui.set_storage_usage(StorageUsage {
+                        available: true,
+                        used: SharedString::from(format_size(user_usage.storage_bytes)),
+                        used_fraction: user_usage.storage_bytes as f32 / limit as f32,
+                        decrypted: SharedString::from(
+                            user_usage
+                                .plaintext_bytes
+                                .map(format_size)
+                                .unwrap_or_default(),
+                        ),
+                        quota: SharedString::from(
+                            user_usage.quota_bytes.map(format_size).unwrap_or_default(),
+                        ),
+                        quota_gib: user_usage
+                            .quota_bytes
+                            .map(|bytes| (bytes / BYTES_PER_GIB) as i32)
+                            .unwrap_or(0),
+                        over_quota: user_usage.over_quota(),
+                        partition: SharedString::from(format!(
+                            "{} of {} used",
+                            format_size(partition_usage.used_bytes()),
+                            format_size(partition_usage.total_bytes)
+                        )),
+                        partition_fraction: partition_usage.used_bytes() as f32
+                            / partition_usage.total_bytes.max(1) as f32,
+                    });
+                }
+                Err(e) => error_toast(&gui, "Failed to compute storage usage", e.into()),
+            }
+        });
+    });
+}
+
+pub fn set_quota(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    gib: i32,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let bytes = if gib > 0 {
+            Some(gib as u64 * BYTES_PER_GIB)
+        } else {
+            None
+        };
+        if let Err(e) = usage::set_quota(&user, bytes) {
+            audit::record(&actor(&session), Action::SetQuota, &user, false);
+            error_toast(&gui, "Failed to set quota", e.into());
+        } else {
+            audit::record(&actor(&session), Action::SetQuota, &user, true);
+        }
+        update_storage_usage(&gui, &user);
+    }
+}
+
+fn update_encryption_details(gui: &CoreSettings, user: &str) {
+    if !gui.get_selected_user().encryption {
+        gui.set_encryption_details(EncryptionDetails::default());
+        return;
+    }
+
+    match libcoresettings::users::encryption_config(&user) {
+        Ok(config) => {
+            let file_names = if config.plaintext_names() {
+                "Not encrypted"
+            } else if config.long_names() {
+                "Encrypted, long names supported"
+            } else {
+                "Encrypted"
+            };
+            let warnings: Vec<SharedString> = config
+                .warnings()
+                .iter()
+                .map(|warning| SharedString::from(warning))
+                .collect();
+            gui.set_encryption_details(EncryptionDetails {
+                available: true,
+                format: SharedString::from(format!(
+                    "Version {} ({})",
+                    config.version, &config.creator
+                )),
+                cipher: SharedString::from(config.content_cipher().as_str()),
+                key_derivation: SharedString::from(format!(
+                    "scrypt N={}, R={}, P={}",
+                    config.scrypt.n, config.scrypt.r, config.scrypt.p
+                )),
+                file_names: SharedString::from(file_names),
+                warnings: ModelRc::new(VecModel::from(warnings)),
+            });
+        }
+        Err(e) => {
+            gui.set_encryption_details(EncryptionDetails::default());
+            error_toast(&gui, "Failed to read encryption details", e.into());
+        }
+    }
 }
 
 pub fn change_user_password(
@@ -68,8 +319,10 @@ pub fn change_user_password(
     mut old_password: SharedString,
     new_password: SharedString,
     encrypted_storage_was_disabled: bool,
+    recovery_code: bool,
     timer: &Rc<Timer>,
     boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
 ) {
     let gui_weak = gui_weak.clone();
     timer.start(
@@ -79,6 +332,12 @@ pub fn change_user_password(
             let boot_config = boot_config.clone();
             move || {
                 if let Some(gui) = gui_weak.upgrade() {
+                    // Without a current password there is nothing to prove the user's identity
+                    if encrypted_storage_was_disabled && !authorize(&gui, &session) {
+                        return;
+                    }
+                    let actor = self_service_actor(&session, &user);
+
                     if encrypted_storage_was_disabled {
                         old_password =
                             SharedString::from(storage_encryption::DISABLED_MODE_PASSWORD);
@@ -90,16 +349,27 @@ pub fn change_user_password(
                         &old_password,
                         Some(&new_password),
                     ) {
-                        error_toast(&gui, "Failed to change user password", e.into());
+                        audit::record(&actor, Action::ChangePassword, &user, false);
+                        storage_error_toast(&gui, &user, "Failed to change user password", e);
                     } else {
                         if let Err(e) = libcoresettings::users::change_encryption_password(
                             &user.to_string(),
                             &old_password.to_string(),
                             &new_password.to_string(),
                         ) {
-                            error_toast(&gui, "Failed to change encryption password", e.into());
+                            audit::record(&actor, Action::ChangePassword, &user, false);
+                            storage_error_toast(
+                                &gui,
+                                &user,
+                                "Failed to change encryption password",
+                                e,
+                            );
                         } else {
+                            audit::record(&actor, Action::ChangePassword, &user, true);
                             toast(&gui, "Password set successfully");
+                            if recovery_code {
+                                show_recovery_code(&gui, &user, &new_password);
+                            }
                         }
                     }
                     refresh_users_ui(&gui, boot_config.clone());
@@ -109,12 +379,201 @@ pub fn change_user_password(
     );
 }
 
+/// Runs in a separate thread so that the UI can report the progress of a migration
+pub fn upgrade_encryption(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    password: SharedString,
+    migrate: bool,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let actor = self_service_actor(&session, &user);
+    let user = user.to_string();
+    let password = password.to_string();
+    std::thread::spawn(move || {
+        let (action, result) = if migrate {
+            let mut last_percentage = None;
+            let result = rekey::migrate(&user, &password, &mut |copied, total| {
+                let percentage = if total == 0 {
+                    100
+                } else {
+                    copied * 100 / total
+                };
+                if last_percentage != Some(percentage) {
+                    last_percentage = Some(percentage);
+                    let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                        gui.set_dialog_message(SharedString::from(format!(
+                            "Migrating encrypted storage: {}%",
+                            percentage
+                        )));
+                    });
+                }
+            });
+            (Action::MigrateEncryption, result)
+        } else {
+            (
+                Action::UpgradeEncryption,
+                rekey::upgrade_scrypt_cost(&user, &password),
+            )
+        };
+
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+            match result {
+                Ok(()) => {
+                    audit::record(&actor, action, &user, true);
+                    if migrate {
+                        toast(
+                            &gui,
+                            "Encrypted storage migrated: previous recovery codes no longer work",
+                        );
+                    } else {
+                        toast(&gui, "Encryption upgraded successfully");
+                    }
+                }
+                Err(e) => {
+                    audit::record(&actor, action, &user, false);
+                    storage_error_toast(&gui, &user, "Failed to upgrade encryption", e);
+                }
+            }
+            refresh_users_ui(&gui, boot_config);
+        });
+    });
+}
+
+/// Runs in a separate thread so that the UI can report the progress of the copy
+pub fn encrypt_home(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    password: SharedString,
+    recovery_code: bool,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    // Unprotected data proves nothing about who is at the device
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+    }
+    let actor = actor(&session);
+    let user = user.to_string();
+    let password = password.to_string();
+    std::thread::spawn(move || {
+        let mut last_percentage = None;
+        let result =
+            libcoresettings::users::encrypt_home(&user, &password, &mut |copied, total| {
+                let percentage = if total == 0 {
+                    100
+                } else {
+                    copied * 100 / total
+                };
+                if last_percentage != Some(percentage) {
+                    last_percentage = Some(percentage);
+                    let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                        gui.set_dialog_message(SharedString::from(format!(
+                            "Encrypting storage: {}%",
+                            percentage
+                        )));
+                    });
+                }
+            });
+
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+            match result {
+                Ok(()) => {
+                    audit::record(&actor, Action::EncryptHome, &user, true);
+                    toast(&gui, "Storage encrypted successfully");
+                    if recovery_code {
+                        show_recovery_code(&gui, &user, &password);
+                    }
+                }
+                Err(e) => {
+                    audit::record(&actor, Action::EncryptHome, &user, false);
+                    storage_error_toast(&gui, &user, "Failed to encrypt storage", e);
+                }
+            }
+            refresh_users_ui(&gui, boot_config);
+        });
+    });
+}
+
+fn show_storage_report(gui: &CoreSettings, report: &Report) {
+    let problems: Vec<SharedString> = report
+        .problems
+        .iter()
+        .map(|problem| SharedString::from(&problem.message))
+        .collect();
+    gui.set_storage_report(ModelRc::new(VecModel::from(problems)));
+    gui.set_storage_report_summary(SharedString::from(&report.summary));
+    gui.set_storage_report_damaged_files(report.damaged_files().len() as i32);
+    gui.set_sticky_toast(false);
+    gui.set_dialog(DialogType::StorageReport);
+}
+
+/// Runs in a separate thread: checking a large home directory takes a while
+pub fn check_storage(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    password: SharedString,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let actor = self_service_actor(&session, &user);
+    let user = user.to_string();
+    let password = password.to_string();
+    std::thread::spawn(move || {
+        let result = fsck::check(&user, &password);
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| match result {
+            Ok(report) => {
+                audit::record(&actor, Action::CheckStorage, &user, true);
+                show_storage_report(&gui, &report);
+            }
+            Err(e) => {
+                audit::record(&actor, Action::CheckStorage, &user, false);
+                storage_error_toast(&gui, &user, "Failed to check storage", e);
+            }
+        });
+    });
+}
+
+pub fn move_damaged_files(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    password: SharedString,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let actor = self_service_actor(&session, &user);
+    let user = user.to_string();
+    let password = password.to_string();
+    std::thread::spawn(move || {
+        let result = fsck::move_damaged_files(&user, &password);
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| match result {
+            Ok((_, moved)) => {
+                audit::record(&actor, Action::MoveDamagedFiles, &user, true);
+                toast(
+                    &gui,
+                    &format!(
+                        "Moved {} damaged file(s) to '{}'",
+                        moved.len(),
+                        &fsck::DAMAGED_FILES_DIR
+                    ),
+                );
+            }
+            Err(e) => {
+                audit::record(&actor, Action::MoveDamagedFiles, &user, false);
+                storage_error_toast(&gui, &user, "Failed to move damaged files", e);
+            }
+        });
+    });
+}
+
 pub fn disable_storage_encryption(
     gui_weak: Weak<CoreSettings>,
     user: SharedString,
     password: SharedString,
     timer: &Rc<Timer>,
     boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
 ) {
     let gui_weak = gui_weak.clone();
     timer.start(
@@ -124,21 +583,27 @@ pub fn disable_storage_encryption(
             let boot_config = boot_config.clone();
             move || {
                 if let Some(gui) = gui_weak.upgrade() {
+                    let actor = self_service_actor(&session, &user);
+
                     if let Err(e) = libcoresettings::users::change_user_password(
                         None,
                         &user,
                         &password.to_string(),
                         Some(&DISABLED_MODE_PASSWORD),
                     ) {
-                        error_toast(&gui, "Failed to change user password", e.into());
+                        audit::record(&actor, Action::DisableEncryption, &user, false);
+                        storage_error_toast(&gui, &user, "Failed to change user password", e);
+                        return;
                     }
 
                     if let Err(e) = libcoresettings::users::disable_encryption(
                         &user.to_string(),
                         &password.to_string(),
                     ) {
-                        error_toast(&gui, "Failed to disable encryption", e.into());
+                        audit::record(&actor, Action::DisableEncryption, &user, false);
+                        storage_error_toast(&gui, &user, "Failed to disable encryption", e);
                     } else {
+                        audit::record(&actor, Action::DisableEncryption, &user, true);
                         toast(&gui, "Encryption successfully disabled");
                     }
                     refresh_users_ui(&gui, boot_config.clone());
@@ -148,16 +613,309 @@ pub fn disable_storage_encryption(
     );
 }
 
+pub fn reset_user_password(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    passphrase: SharedString,
+    new_password: SharedString,
+    timer: &Rc<Timer>,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let gui_weak = gui_weak.clone();
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        {
+            let boot_config = boot_config.clone();
+            move || {
+                if let Some(gui) = gui_weak.upgrade() {
+                    if !authorize(&gui, &session) {
+                        return;
+                    }
+
+                    // The recovery private key is expected on removable storage
+                    if let Err(e) = removable::first_mountpoint().and_then(|dir| {
+                        libcoresettings::users::reset_password(
+                            &user,
+                            &format!("{}/{}", &dir, &escrow::RECOVERY_PRIVATE_KEY_FILE_NAME),
+                            &passphrase,
+                            &new_password,
+                        )
+                    }) {
+                        audit::record(&actor(&session), Action::ResetPassword, &user, false);
+                        storage_error_toast(&gui, &user, "Failed to reset password", e);
+                    } else {
+                        audit::record(&actor(&session), Action::ResetPassword, &user, true);
+                        toast(&gui, "Password reset successfully");
+                    }
+                    refresh_users_ui(&gui, boot_config.clone());
+                }
+            }
+        },
+    );
+}
+
+pub fn recover_user_password(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    recovery_code: SharedString,
+    new_password: SharedString,
+    timer: &Rc<Timer>,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let gui_weak = gui_weak.clone();
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        {
+            let boot_config = boot_config.clone();
+            move || {
+                if let Some(gui) = gui_weak.upgrade() {
+                    // Users recover their own password: the recovery code is checked against their storage
+                    // instead, and wrong codes count towards the same lockout as wrong passwords
+                    let actor = self_service_actor(&session, &user);
+
+                    if let Err(e) = libcoresettings::users::recover_password(
+                        &user,
+                        &recovery_code,
+                        &new_password,
+                    ) {
+                        audit::record(&actor, Action::RecoverPassword, &user, false);
+                        storage_error_toast(&gui, &user, "Failed to recover password", e);
+                    } else {
+                        audit::record(&actor, Action::RecoverPassword, &user, true);
+                        toast(&gui, "Password set successfully");
+                    }
+                    refresh_users_ui(&gui, boot_config.clone());
+                }
+            }
+        },
+    );
+}
+
+fn show_recovery_code(gui: &CoreSettings, user: &str, password: &str) {
+    match libcoresettings::users::recovery_code(&user, &password).and_then(|code| {
+        let qr_code = qr_code_image(&code)?;
+        Ok((code, qr_code))
+    }) {
+        Ok((code, qr_code)) => {
+            gui.set_recovery_code(SharedString::from(&code));
+            gui.set_recovery_code_qr(qr_code);
+            gui.set_sticky_toast(false);
+            gui.set_dialog(DialogType::RecoveryCode);
+        }
+        Err(e) => error_toast(&gui, "Failed to generate recovery code", e.into()),
+    }
+}
+
+pub fn clear_recovery_code(gui: &CoreSettings) {
+    gui.set_recovery_code(SharedString::new());
+    gui.set_recovery_code_qr(Image::default());
+    gui.set_dialog(DialogType::None);
+}
+
+pub fn create_recovery_key(
+    gui_weak: Weak<CoreSettings>,
+    passphrase: SharedString,
+    timer: &Rc<Timer>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let gui_weak = gui_weak.clone();
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                if !authorize(&gui, &session) {
+                    return;
+                }
+
+                match removable::first_mountpoint()
+                    .and_then(|dir| escrow::create_recovery_key(&dir, &passphrase))
+                {
+                    Ok(path) => {
+                        audit::record(&actor(&session), Action::CreateRecoveryKey, &path, true);
+                        toast(
+                            &gui,
+                            &format!("Recovery key written to {}: keep it safe", &path),
+                        );
+                    }
+                    Err(e) => {
+                        audit::record(&actor(&session), Action::CreateRecoveryKey, "", false);
+                        error_toast(&gui, "Failed to create recovery key", e.into());
+                    }
+                }
+            }
+        },
+    );
+}
+
+/// Runs in a separate thread: processes get some time to exit cleanly
+pub fn end_user_sessions(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+    }
+    let actor = actor(&session);
+    let user = user.to_string();
+    std::thread::spawn(move || {
+        let result = activity::release(&user);
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+            match result {
+                Ok(()) => {
+                    audit::record(&actor, Action::EndSessions, &user, true);
+                    toast(&gui, "Sessions ended: please try again");
+                }
+                Err(e) => {
+                    audit::record(&actor, Action::EndSessions, &user, false);
+                    error_toast(&gui, "Failed to end sessions", e.into());
+                }
+            }
+            refresh_users_ui(&gui, boot_config);
+        });
+    });
+}
+
+/// Backups are written in a separate thread: the encrypted storage is copied as a whole
+pub fn backup_user(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+        gui.set_dialog_message(SharedString::from("Backing up user"));
+        gui.set_sticky_toast(true);
+        gui.set_dialog(DialogType::Toast);
+    }
+    let actor = actor(&session);
+    let user = user.to_string();
+    std::thread::spawn(move || {
+        let result = removable::first_mountpoint().and_then(|dir| backup::export(&user, &dir));
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| match result {
+            Ok(path) => {
+                audit::record(&actor, Action::BackupUser, &user, true);
+                toast(&gui, &format!("Backup written to {}", &path));
+            }
+            Err(e) => {
+                audit::record(&actor, Action::BackupUser, &user, false);
+                storage_error_toast(&gui, &user, "Failed to back up user", e);
+            }
+        });
+    });
+}
+
+pub fn get_backups(gui: &CoreSettings) {
+    let backups: Vec<BackupEntry> = match backup::list_removable() {
+        Ok(backups) => backups
+            .iter()
+            .filter_map(|path| {
+                let manifest = backup::read_manifest(&path).ok()?;
+                Some(BackupEntry {
+                    path: SharedString::from(path),
+                    label: SharedString::from(format!(
+                        "{} ({})",
+                        &manifest.user,
+                        time::format_timestamp(manifest.created)
+                    )),
+                })
+            })
+            .collect(),
+        Err(e) => {
+            error_toast(&gui, "Failed to look for backups", e.into());
+            Vec::new()
+        }
+    };
+    gui.set_backups(ModelRc::new(VecModel::from(backups)));
+}
+
+/// During setup, the restored user becomes the default administrator and the application quits afterwards
+pub fn restore_user(
+    gui_weak: Weak<CoreSettings>,
+    backup_path: SharedString,
+    password: SharedString,
+    quit_afterwards: bool,
+    quit_sender: Sender<()>,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+    }
+    let actor = actor(&session);
+    let backup_path = backup_path.to_string();
+    let password = password.to_string();
+    std::thread::spawn(move || {
+        let result = backup::import(
+            &backup_path,
+            &password,
+            quit_afterwards,
+            quit_afterwards,
+            boot_config.clone(),
+        );
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| match result {
+            Ok(user) => {
+                audit::record(&actor, Action::RestoreUser, &user, true);
+                if quit_afterwards {
+                    let _ = quit_sender.send(());
+                    return;
+                }
+                refresh_users_ui(&gui, boot_config);
+                toast(&gui, &format!("User '{}' restored successfully", &user));
+            }
+            Err(e) => {
+                audit::record(&actor, Action::RestoreUser, &backup_path, false);
+                error_toast(&gui, "Failed to restore user", e.into());
+            }
+        });
+    });
+}
+
+pub fn get_profiles(gui: &CoreSettings) {
+    let profiles: Vec<UserProfile> = match profiles::list() {
+        Ok(profiles) => profiles
+            .iter()
+            .map(|profile| UserProfile {
+                name: SharedString::from(&profile.name),
+                description: SharedString::from(&profile.description),
+                admin: profile.admin.unwrap_or(false),
+                encryption: profile.encryption.unwrap_or(true),
+            })
+            .collect(),
+        Err(e) => {
+            error_toast(&gui, "Failed to read user profiles", e.into());
+            Vec::new()
+        }
+    };
+    gui.set_profiles(ModelRc::new(VecModel::from(profiles)));
+}
+
 pub fn create(
     gui_weak: Weak<CoreSettings>,
     username: SharedString,
     password: SharedString,
     admin: bool,
     make_default: bool,
+    recovery_code: bool,
+    encrypt: bool,
+    profile: SharedString,
     timer: &Rc<Timer>,
     quit_sender: Sender<()>,
     quit_afterwards: bool,
     boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
 ) {
     let gui_weak = gui_weak.clone();
     timer.start(
@@ -167,21 +925,46 @@ pub fn create(
             let boot_config = boot_config.clone();
             move || {
                 if let Some(gui) = gui_weak.upgrade() {
-                    if let Err(e) = libcoresettings::users::create(
-                        &username,
-                        &password,
-                        admin,
-                        make_default,
-                        boot_config.clone(),
-                    ) {
+                    if !authorize_user_creation(&gui, &session) {
+                        return;
+                    }
+
+                    let result = if profile.is_empty() {
+                        Ok(None)
+                    } else {
+                        profiles::get(&profile).map(Some)
+                    };
+                    if let Err(e) = result.and_then(|profile| {
+                        libcoresettings::users::create(
+                            &username,
+                            &password,
+                            admin,
+                            make_default,
+                            encrypt,
+                            profile.as_ref(),
+                            boot_config.clone(),
+                        )
+                    }) {
+                        audit::record(&actor(&session), Action::CreateUser, &username, false);
                         error_toast(&gui, "Failed to create user", e.into());
                     } else if quit_afterwards {
+                        audit::record(&actor(&session), Action::CreateUser, &username, true);
                         let _ = quit_sender.send(());
                     } else {
-                        gui.set_admin_lock_set(false);
+                        audit::record(&actor(&session), Action::CreateUser, &username, true);
+                        // The first administrator created gets to manage the system right away
+                        let mut session = session.lock().unwrap();
+                        if !session.is_unlocked() && admin {
+                            session.unlock(&username);
+                        }
+                        gui.set_admin_lock_set(!session.is_unlocked());
+                        drop(session);
                         refresh_users_ui(&gui, boot_config.clone());
                         gui.set_sticky_toast(false);
                         toast(&gui, "User created successfully");
+                        if recovery_code {
+                            show_recovery_code(&gui, &username, &password);
+                        }
                     }
                 }
             }
@@ -194,6 +977,7 @@ pub fn admin_login_verify(
     username: &str,
     password: &str,
     timer: &Rc<Timer>,
+    session: Arc<Mutex<AdminSession>>,
 ) {
     let gui_weak = gui_weak.clone();
     let username = username.to_owned();
@@ -204,13 +988,31 @@ pub fn admin_login_verify(
         {
             move || {
                 if let Some(gui) = gui_weak.upgrade() {
-                    match libcoresettings::users::admin_login_verify(&username, &password) {
+                    let status = libcoresettings::users::admin_login_verify(&username, &password);
+                    audit::record(
+                        &username,
+                        Action::AdminLogin,
+                        &username,
+                        matches!(status, AdminLoginStatus::Success),
+                    );
+                    match status {
                         AdminLoginStatus::Success => {
+                            session.lock().unwrap().unlock(&username);
                             gui.set_admin_lock_set(false);
                             toast(&gui, "Login successful");
                         }
                         AdminLoginStatus::NotAdmin => toast(&gui, "Administrator user not found"),
-                        AdminLoginStatus::Failure => toast(&gui, "Login failed"),
+                        AdminLoginStatus::Failure => {
+                            if let Ok(Some(retry_after)) = lockout::retry_after(&username) {
+                                gui.set_lockout_seconds(retry_after.as_secs() as i32);
+                            }
+                            toast(&gui, "Login failed");
+                        }
+                        AdminLoginStatus::LockedOut { retry_after } => {
+                            gui.set_lockout_seconds(retry_after.as_secs() as i32);
+                            gui.set_sticky_toast(false);
+                            gui.set_dialog(DialogType::AdminLogin);
+                        }
                     }
                 }
             }
@@ -218,11 +1020,144 @@ pub fn admin_login_verify(
     )
 }
 
+/// Administrators who can unlock with a PIN instead of their password
+pub fn get_pin_admins(gui: &CoreSettings) {
+    let admins: Vec<SharedString> = match libcoresettings::users::admin_users() {
+        Ok(admins) => admins
+            .iter()
+            .filter(|admin| pin::is_set(&admin))
+            .map(|admin| SharedString::from(admin))
+            .collect(),
+        Err(e) => {
+            error!("Failed to list administrators: {}", e);
+            Vec::new()
+        }
+    };
+    gui.set_pin_admins(ModelRc::new(VecModel::from(admins)));
+}
+
+pub fn admin_pin_login_verify(
+    gui_weak: Weak<CoreSettings>,
+    username: &str,
+    pin: &str,
+    timer: &Rc<Timer>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let gui_weak = gui_weak.clone();
+    let username = username.to_owned();
+    let pin = pin.to_owned();
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        {
+            move || {
+                if let Some(gui) = gui_weak.upgrade() {
+                    if !is_admin(&username) {
+                        toast(&gui, "Administrator user not found");
+                        return;
+                    }
+
+                    let status = match pin::verify(&username, &pin) {
+                        Ok(status) => status,
+                        Err(e) => {
+                            error_toast(&gui, "Failed to verify PIN", e.into());
+                            return;
+                        }
+                    };
+                    audit::record(
+                        &username,
+                        Action::AdminPinLogin,
+                        &username,
+                        matches!(status, PinStatus::Success),
+                    );
+                    match status {
+                        PinStatus::Success => {
+                            session.lock().unwrap().unlock(&username);
+                            gui.set_admin_lock_set(false);
+                            toast(&gui, "Login successful");
+                        }
+                        PinStatus::Failure { remaining_attempts } => toast(
+                            &gui,
+                            &format!("Wrong PIN: {} attempt(s) left", remaining_attempts),
+                        ),
+                        PinStatus::Erased => {
+                            get_pin_admins(&gui);
+                            toast(&gui, "Too many wrong PINs: log in with your password")
+                        }
+                        PinStatus::NotSet => toast(&gui, "No PIN set for this user"),
+                        PinStatus::LockedOut { retry_after } => {
+                            gui.set_lockout_seconds(retry_after.as_secs() as i32);
+                            gui.set_sticky_toast(false);
+                            gui.set_dialog(DialogType::AdminPinLogin);
+                        }
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// The user's password proves their identity, so no administrator session is needed
+pub fn set_pin(
+    gui_weak: Weak<CoreSettings>,
+    user: SharedString,
+    password: SharedString,
+    pin: SharedString,
+    timer: &Rc<Timer>,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    let gui_weak = gui_weak.clone();
+    timer.start(
+        TimerMode::SingleShot,
+        std::time::Duration::from_millis(100),
+        {
+            move || {
+                if let Some(gui) = gui_weak.upgrade() {
+                    let actor = self_service_actor(&session, &user);
+                    if let Err(e) = pin::set(&user, &password, &pin) {
+                        audit::record(&actor, Action::SetPin, &user, false);
+                        password_error_toast(&gui, "Failed to set PIN", e);
+                    } else {
+                        audit::record(&actor, Action::SetPin, &user, true);
+                        toast(&gui, "PIN set successfully");
+                    }
+                    refresh_users_ui(&gui, boot_config.clone());
+                }
+            }
+        },
+    );
+}
+
+pub fn remove_pin(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        if let Err(e) = pin::remove(&user) {
+            audit::record(&actor(&session), Action::RemovePin, &user, false);
+            error_toast(&gui, "Failed to remove PIN", e.into());
+        } else {
+            audit::record(&actor(&session), Action::RemovePin, &user, true);
+            toast(&gui, "PIN removed");
+        }
+        refresh_users_ui(&gui, boot_config);
+    }
+}
+
 pub fn delete(
     gui_weak: Weak<CoreSettings>,
     user: &str,
+    secure: bool,
     timer: &Rc<Timer>,
     boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
 ) {
     let gui_weak = gui_weak.clone();
     let user = user.to_owned();
@@ -232,9 +1167,46 @@ pub fn delete(
         {
             move || {
                 if let Some(gui) = gui_weak.upgrade() {
-                    if let Err(e) = libcoresettings::users::delete(&user) {
-                        error_toast(&gui, "Failed to delete user", e.into());
+                    if !authorize(&gui, &session) {
+                        return;
+                    }
+
+                    if secure {
+                        match libcoresettings::users::secure_delete(&user, boot_config.clone()) {
+                            Ok(wiped) => {
+                                audit::record(
+                                    &actor(&session),
+                                    Action::SecureDeleteUser,
+                                    &user,
+                                    true,
+                                );
+                                toast(
+                                    &gui,
+                                    &format!("User deleted securely. Wiped: {}", wiped.join(", ")),
+                                );
+                            }
+                            Err(e) => {
+                                audit::record(
+                                    &actor(&session),
+                                    Action::SecureDeleteUser,
+                                    &user,
+                                    false,
+                                );
+                                storage_error_toast(
+                                    &gui,
+                                    &user,
+                                    "Failed to delete user securely",
+                                    e,
+                                );
+                            }
+                        }
+                    } else if let Err(e) =
+                        libcoresettings::users::delete(&user, boot_config.clone())
+                    {
+                        audit::record(&actor(&session), Action::DeleteUser, &user, false);
+                        storage_error_toast(&gui, &user, "Failed to delete user", e);
                     } else {
+                        audit::record(&actor(&session), Action::DeleteUser, &user, true);
                         toast(&gui, "User deleted successfully");
                     }
                     gui.set_selected_user(SystemUser {
@@ -243,6 +1215,11 @@ pub fn delete(
                         encryption: false,
                         name: SharedString::from(String::new()),
                         salt: SharedString::from(String::new()),
+                        escrowed: false,
+                        pin: false,
+                        hidden: false,
+                        display_name: SharedString::new(),
+                        password_changed: SharedString::new(),
                     });
                     refresh_users_ui(&gui, boot_config.clone());
                 }
@@ -251,20 +1228,44 @@ pub fn delete(
     );
 }
 
-pub fn make_admin(gui_weak: Weak<CoreSettings>, user: &str, boot_config: Arc<Mutex<BootConfig>>) {
+pub fn make_admin(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
     if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
         if let Err(e) = libcoresettings::users::change_admin_status(&user, true) {
+            audit::record(&actor(&session), Action::GrantAdmin, &user, false);
             error_toast(&gui, &FAILED_ADMIN_STATUS_TOGGLE, e.into());
+        } else {
+            audit::record(&actor(&session), Action::GrantAdmin, &user, true);
         }
         refresh_users_ui(&gui, boot_config.clone());
     }
 }
 
-pub fn remove_admin(gui_weak: Weak<CoreSettings>, user: &str, boot_config: Arc<Mutex<BootConfig>>) {
+pub fn remove_admin(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
     if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
         if sufficient_number_of_admin_users_remaining(&gui) {
             if let Err(e) = libcoresettings::users::change_admin_status(&user, false) {
+                audit::record(&actor(&session), Action::RevokeAdmin, &user, false);
                 error_toast(&gui, &FAILED_ADMIN_STATUS_TOGGLE, e.into());
+            } else {
+                audit::record(&actor(&session), Action::RevokeAdmin, &user, true);
             }
             refresh_users_ui(&gui, boot_config.clone());
         } else {
@@ -273,17 +1274,182 @@ pub fn remove_admin(gui_weak: Weak<CoreSettings>, user: &str, boot_config: Arc<M
     }
 }
 
-pub fn set_default(gui_weak: Weak<CoreSettings>, user: &str, boot_config: Arc<Mutex<BootConfig>>) {
+pub fn set_default(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
     if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
         if user.is_empty() {
             libcoresettings::users::set_default(None, boot_config.clone());
         } else {
             libcoresettings::users::set_default(Some(&user), boot_config.clone());
         }
+        audit::record(&actor(&session), Action::SetDefaultUser, &user, true);
         refresh_users_ui(&gui, boot_config.clone());
     }
 }
 
+pub fn set_auto_login(
+    gui_weak: Weak<CoreSettings>,
+    enabled: bool,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let target = format!(
+            "{} ({})",
+            boot_config
+                .lock()
+                .unwrap()
+                .system
+                .default_user
+                .clone()
+                .unwrap_or_default(),
+            if enabled { "on" } else { "off" }
+        );
+        if let Err(e) = autologin::set(enabled, boot_config.clone()) {
+            audit::record(&actor(&session), Action::SetAutoLogin, &target, false);
+            error_toast(&gui, &e.to_string(), e);
+        } else {
+            audit::record(&actor(&session), Action::SetAutoLogin, &target, true);
+        }
+        refresh_users_ui(&gui, boot_config.clone());
+    }
+}
+
+fn listed_users(gui: &CoreSettings) -> Vec<String> {
+    gui.get_users()
+        .iter()
+        .map(|user| user.to_string())
+        .collect()
+}
+
+/// Moves the user up (negative `offset`) or down the login screen
+pub fn move_login_screen_user(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    offset: i32,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        if let Err(e) =
+            loginscreen::move_user(&user, offset, &listed_users(&gui), boot_config.clone())
+        {
+            audit::record(&actor(&session), Action::MoveLoginScreenUser, &user, false);
+            error_toast(&gui, "Failed to move user", e.into());
+        } else {
+            audit::record(&actor(&session), Action::MoveLoginScreenUser, &user, true);
+        }
+        refresh_users_ui(&gui, boot_config.clone());
+    }
+}
+
+pub fn set_login_screen_hidden(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    hidden: bool,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        let target = format!("{} ({})", &user, if hidden { "hidden" } else { "shown" });
+        if let Err(e) =
+            loginscreen::set_hidden(&user, hidden, &listed_users(&gui), boot_config.clone())
+        {
+            audit::record(
+                &actor(&session),
+                Action::SetLoginScreenHidden,
+                &target,
+                false,
+            );
+            error_toast(&gui, &e.to_string(), e);
+        } else {
+            audit::record(
+                &actor(&session),
+                Action::SetLoginScreenHidden,
+                &target,
+                true,
+            );
+        }
+        refresh_users_ui(&gui, boot_config.clone());
+    }
+}
+
+pub fn set_display_name(
+    gui_weak: Weak<CoreSettings>,
+    user: &str,
+    display_name: &str,
+    boot_config: Arc<Mutex<BootConfig>>,
+    session: Arc<Mutex<AdminSession>>,
+) {
+    if let Some(gui) = gui_weak.upgrade() {
+        if !authorize(&gui, &session) {
+            return;
+        }
+
+        if let Err(e) = loginscreen::set_display_name(&user, &display_name, boot_config.clone()) {
+            audit::record(&actor(&session), Action::SetDisplayName, &user, false);
+            error_toast(&gui, &e.to_string(), e);
+        } else {
+            audit::record(&actor(&session), Action::SetDisplayName, &user, true);
+        }
+        refresh_users_ui(&gui, boot_config.clone());
+    }
+}
+
+/// Users may change their own password and disable their own encryption without an administrator session:
+/// these actions verify the current password first. Returns who to attribute the action to.
+fn self_service_actor(session: &Arc<Mutex<AdminSession>>, user: &str) -> String {
+    if session.lock().unwrap().touch() {
+        actor(&session)
+    } else {
+        user.to_string()
+    }
+}
+
+/// Offers to end what keeps the user's storage busy, instead of only reporting it
+fn storage_error_toast(gui: &CoreSettings, user: &str, message: &str, e: anyhow::Error) {
+    if let Some(in_use) = e.downcast_ref::<InUse>() {
+        gui.set_user_in_use(SharedString::from(user));
+        gui.set_dialog_message(SharedString::from(format!(
+            "{}.\n\nEnd them and unmount the storage?",
+            &in_use
+        )));
+        gui.set_sticky_toast(false);
+        gui.set_dialog(DialogType::ConfirmEndSessions);
+        error!("{}: {}", &message, &in_use);
+    } else {
+        password_error_toast(&gui, &message, e);
+    }
+}
+
+fn password_error_toast(gui: &CoreSettings, message: &str, e: anyhow::Error) {
+    if let Some(locked_out) = e.downcast_ref::<LockedOut>() {
+        toast(&gui, &locked_out.to_string());
+    } else {
+        error_toast(&gui, &message, e);
+    }
+}
+
 fn sufficient_number_of_admin_users_remaining(gui: &CoreSettings) -> bool {
     match libcoresettings::users::count_admin_users() {
         Ok(count) => return count >= 2,
@@ -295,10 +1461,12 @@ fn sufficient_number_of_admin_users_remaining(gui: &CoreSettings) -> bool {
 }
 
 fn refresh_users_ui(gui: &CoreSettings, boot_config: Arc<Mutex<BootConfig>>) {
-    get_users(&gui, boot_config);
+    // Any change to the default user or their storage may rule auto-login out
+    autologin::validate(boot_config.clone());
+    get_users(&gui, boot_config.clone());
 
     let selected_user = gui.get_selected_user();
     if !selected_user.name.is_empty() {
-        get_user_details(&gui, selected_user.name);
+        update_user_details(&gui, selected_user.name, boot_config);
     }
 }
@@ -8,8 +8,9 @@ use std::{
 };
 
 use anyhow::{Context, Result};
+use libcoresettings::{autologin, config::Config, session::AdminSession};
 use libqinit::boot_config::BootConfig;
-use log::info;
+use log::{error, info};
 use slint::{Timer, TimerMode};
 slint::include_modules!();
 
@@ -26,6 +27,13 @@ fn main() -> Result<()> {
     info!("Original boot configuration: {:?}", &original_boot_config);
     let boot_config = Arc::new(Mutex::new(original_boot_config.clone()));
 
+    // Administrator session
+    let config = Config::read().unwrap_or_else(|e| {
+        error!("Failed to read configuration, using defaults: {}", e);
+        Config::default()
+    });
+    let session = Arc::new(Mutex::new(AdminSession::new(config.admin_idle_timeout)));
+
     // GUI
     let gui = CoreSettings::new().with_context(|| "Failed to initialize Slint UI")?;
     let gui_weak = gui.as_weak();
@@ -45,10 +53,35 @@ fn main() -> Result<()> {
         },
     );
 
+    let lockout_countdown_timer = Timer::default();
+    lockout_countdown_timer.start(TimerMode::Repeated, std::time::Duration::from_secs(1), {
+        let gui_weak = gui_weak.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                let lockout_seconds = gui.get_lockout_seconds();
+                if lockout_seconds > 0 {
+                    gui.set_lockout_seconds(lockout_seconds - 1);
+                }
+            }
+        }
+    });
+
+    let admin_session_timer = Timer::default();
+    admin_session_timer.start(TimerMode::Repeated, std::time::Duration::from_secs(1), {
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::admin_session_timer_check(&gui, &session);
+            }
+        }
+    });
+
     // OOBE
     {
         let locked_boot_config = boot_config.lock().unwrap().clone();
         if !locked_boot_config.flags.first_boot_done {
+            gui_fn::users::get_profiles(&gui);
             gui.set_page(Page::OOBE);
         } else {
             gui.set_page(Page::SettingsMenu);
@@ -68,9 +101,11 @@ fn main() -> Result<()> {
 
     gui.on_get_selected_user_details({
         let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
         move |user| {
             if let Some(gui) = gui_weak.upgrade() {
-                gui_fn::users::get_user_details(&gui, user)
+                gui_fn::users::get_user_details(&gui, user, boot_config.clone(), &session)
             }
         }
     });
@@ -79,15 +114,18 @@ fn main() -> Result<()> {
     gui.on_change_user_password({
         let gui_weak = gui_weak.clone();
         let boot_config = boot_config.clone();
-        move |user, old_password, new_password, encrypted_storage_was_disabled| {
+        let session = session.clone();
+        move |user, old_password, new_password, encrypted_storage_was_disabled, recovery_code| {
             gui_fn::users::change_user_password(
                 gui_weak.clone(),
                 user,
                 old_password,
                 new_password,
                 encrypted_storage_was_disabled,
+                recovery_code,
                 &encryption_change_password_timer,
                 boot_config.clone(),
+                session.clone(),
             )
         }
     });
@@ -96,6 +134,7 @@ fn main() -> Result<()> {
     gui.on_disable_storage_encryption({
         let gui_weak = gui_weak.clone();
         let boot_config = boot_config.clone();
+        let session = session.clone();
         move |user, password| {
             gui_fn::users::disable_storage_encryption(
                 gui_weak.clone(),
@@ -103,6 +142,172 @@ fn main() -> Result<()> {
                 password,
                 &encryption_disable_timer,
                 boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_upgrade_encryption({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, password, migrate| {
+            gui_fn::users::upgrade_encryption(
+                gui_weak.clone(),
+                user,
+                password,
+                migrate,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_encrypt_home({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, password, recovery_code| {
+            gui_fn::users::encrypt_home(
+                gui_weak.clone(),
+                user,
+                password,
+                recovery_code,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_check_storage({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, password| {
+            gui_fn::users::check_storage(gui_weak.clone(), user, password, session.clone());
+        }
+    });
+
+    gui.on_move_damaged_files({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, password| {
+            gui_fn::users::move_damaged_files(gui_weak.clone(), user, password, session.clone());
+        }
+    });
+
+    let reset_user_password_timer = Rc::new(Timer::default());
+    gui.on_reset_user_password({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, passphrase, new_password| {
+            gui_fn::users::reset_user_password(
+                gui_weak.clone(),
+                user,
+                passphrase,
+                new_password,
+                &reset_user_password_timer,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    let recover_user_password_timer = Rc::new(Timer::default());
+    gui.on_recover_user_password({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, recovery_code, new_password| {
+            gui_fn::users::recover_user_password(
+                gui_weak.clone(),
+                user,
+                recovery_code,
+                new_password,
+                &recover_user_password_timer,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_clear_recovery_code({
+        let gui_weak = gui_weak.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::users::clear_recovery_code(&gui);
+            }
+        }
+    });
+
+    let create_recovery_key_timer = Rc::new(Timer::default());
+    gui.on_create_recovery_key({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |passphrase| {
+            gui_fn::users::create_recovery_key(
+                gui_weak.clone(),
+                passphrase,
+                &create_recovery_key_timer,
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_end_user_sessions({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user| {
+            gui_fn::users::end_user_sessions(
+                gui_weak.clone(),
+                user,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_backup_user({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user| {
+            gui_fn::users::backup_user(gui_weak.clone(), user, session.clone());
+        }
+    });
+
+    gui.on_get_profiles({
+        let gui_weak = gui_weak.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::users::get_profiles(&gui);
+            }
+        }
+    });
+
+    gui.on_get_backups({
+        let gui_weak = gui_weak.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::users::get_backups(&gui);
+            }
+        }
+    });
+
+    gui.on_restore_user({
+        let quit_sender = quit_sender.clone();
+        let boot_config = boot_config.clone();
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |backup, password, quit_afterwards| {
+            gui_fn::users::restore_user(
+                gui_weak.clone(),
+                backup,
+                password,
+                quit_afterwards,
+                quit_sender.clone(),
+                boot_config.clone(),
+                session.clone(),
             );
         }
     });
@@ -112,17 +317,29 @@ fn main() -> Result<()> {
         let quit_sender = quit_sender.clone();
         let boot_config = boot_config.clone();
         let gui_weak = gui_weak.clone();
-        move |username, password, admin, quit_afterwards, make_default| {
+        let session = session.clone();
+        move |username,
+              password,
+              admin,
+              quit_afterwards,
+              make_default,
+              recovery_code,
+              encrypt,
+              profile| {
             gui_fn::users::create(
                 gui_weak.clone(),
                 username,
                 password,
                 admin,
                 make_default,
+                recovery_code,
+                encrypt,
+                profile,
                 &create_user_timer,
                 quit_sender.clone(),
                 quit_afterwards,
                 boot_config.clone(),
+                session.clone(),
             );
         }
     });
@@ -149,26 +366,98 @@ fn main() -> Result<()> {
     let admin_login_verify_timer = Rc::new(Timer::default());
     gui.on_admin_login_verify({
         let gui_weak = gui_weak.clone();
+        let session = session.clone();
         move |username, password| {
             gui_fn::users::admin_login_verify(
                 gui_weak.clone(),
                 &username.to_string(),
                 &password.to_string(),
                 &admin_login_verify_timer,
+                session.clone(),
+            );
+        }
+    });
+
+    let admin_pin_login_verify_timer = Rc::new(Timer::default());
+    gui.on_admin_pin_login_verify({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |username, pin| {
+            gui_fn::users::admin_pin_login_verify(
+                gui_weak.clone(),
+                &username.to_string(),
+                &pin.to_string(),
+                &admin_pin_login_verify_timer,
+                session.clone(),
             );
         }
     });
 
+    gui.on_get_pin_admins({
+        let gui_weak = gui_weak.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::users::get_pin_admins(&gui);
+            }
+        }
+    });
+
+    let set_user_pin_timer = Rc::new(Timer::default());
+    gui.on_set_user_pin({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, password, pin| {
+            gui_fn::users::set_pin(
+                gui_weak.clone(),
+                user,
+                password,
+                pin,
+                &set_user_pin_timer,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_remove_user_pin({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user| {
+            gui_fn::users::remove_pin(
+                gui_weak.clone(),
+                &user,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_lock_admin_session({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move || {
+            session.lock().unwrap().lock();
+            if let Some(gui) = gui_weak.upgrade() {
+                gui.set_admin_lock_set(true);
+            }
+        }
+    });
+
     let delete_user_timer = Rc::new(Timer::default());
     gui.on_delete_user({
         let gui_weak = gui_weak.clone();
         let boot_config = boot_config.clone();
-        move |user| {
+        let session = session.clone();
+        move |user, secure| {
             gui_fn::users::delete(
                 gui_weak.clone(),
                 &user.to_string(),
+                secure,
                 &delete_user_timer,
                 boot_config.clone(),
+                session.clone(),
             )
         }
     });
@@ -176,25 +465,261 @@ fn main() -> Result<()> {
     gui.on_make_admin({
         let gui_weak = gui_weak.clone();
         let boot_config = boot_config.clone();
-        move |user| gui_fn::users::make_admin(gui_weak.clone(), &user, boot_config.clone())
+        let session = session.clone();
+        move |user| {
+            gui_fn::users::make_admin(
+                gui_weak.clone(),
+                &user,
+                boot_config.clone(),
+                session.clone(),
+            )
+        }
     });
 
     gui.on_remove_admin({
         let gui_weak = gui_weak.clone();
         let boot_config = boot_config.clone();
+        let session = session.clone();
         move |user| {
-            gui_fn::users::remove_admin(gui_weak.clone(), &user, boot_config.clone());
+            gui_fn::users::remove_admin(
+                gui_weak.clone(),
+                &user,
+                boot_config.clone(),
+                session.clone(),
+            );
         }
     });
 
     gui.on_set_default_user({
         let gui_weak = gui_weak.clone();
         let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user| {
+            gui_fn::users::set_default(
+                gui_weak.clone(),
+                &user.to_string(),
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_move_login_screen_user({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, offset| {
+            gui_fn::users::move_login_screen_user(
+                gui_weak.clone(),
+                &user.to_string(),
+                offset,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_set_login_screen_hidden({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, hidden| {
+            gui_fn::users::set_login_screen_hidden(
+                gui_weak.clone(),
+                &user.to_string(),
+                hidden,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_set_display_name({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |user, display_name| {
+            gui_fn::users::set_display_name(
+                gui_weak.clone(),
+                &user.to_string(),
+                &display_name.to_string(),
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_set_auto_login({
+        let gui_weak = gui_weak.clone();
+        let boot_config = boot_config.clone();
+        let session = session.clone();
+        move |enabled| {
+            gui_fn::users::set_auto_login(
+                gui_weak.clone(),
+                enabled,
+                boot_config.clone(),
+                session.clone(),
+            );
+        }
+    });
+
+    gui.on_set_user_quota({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, gib| {
+            gui_fn::users::set_quota(gui_weak.clone(), &user.to_string(), gib, session.clone());
+        }
+    });
+
+    gui.on_get_groups({
+        let gui_weak = gui_weak.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::groups::get_groups(&gui)
+            }
+        }
+    });
+
+    gui.on_get_selected_group_details({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |group| {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::groups::get_group_details(&gui, group, &session)
+            }
+        }
+    });
+
+    let create_group_timer = Rc::new(Timer::default());
+    gui.on_create_group({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |name| {
+            gui_fn::groups::create(gui_weak.clone(), name, &create_group_timer, session.clone())
+        }
+    });
+
+    let delete_group_timer = Rc::new(Timer::default());
+    gui.on_delete_group({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |name| {
+            gui_fn::groups::delete(
+                gui_weak.clone(),
+                &name.to_string(),
+                &delete_group_timer,
+                session.clone(),
+            )
+        }
+    });
+
+    gui.on_add_to_group({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, group| {
+            gui_fn::groups::set_membership(gui_weak.clone(), &user, &group, true, session.clone())
+        }
+    });
+
+    gui.on_remove_from_group({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, group| {
+            gui_fn::groups::set_membership(gui_weak.clone(), &user, &group, false, session.clone())
+        }
+    });
+
+    gui.on_set_admin_group({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |group, admin| {
+            gui_fn::groups::set_admin_group(gui_weak.clone(), &group, admin, session.clone())
+        }
+    });
+
+    gui.on_set_privilege_escalation({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |privilege_escalation| {
+            gui_fn::groups::set_privilege_escalation(
+                gui_weak.clone(),
+                privilege_escalation,
+                session.clone(),
+            )
+        }
+    });
+
+    gui.on_get_user_restrictions({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
         move |user| {
-            gui_fn::users::set_default(gui_weak.clone(), &user.to_string(), boot_config.clone());
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::restrictions::get_restrictions(&gui, user, &session)
+            }
+        }
+    });
+
+    gui.on_set_user_restrictions({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, restricted, daily_limit_minutes, start_hour, end_hour| {
+            gui_fn::restrictions::set_restrictions(
+                gui_weak.clone(),
+                &user,
+                restricted,
+                daily_limit_minutes,
+                start_hour,
+                end_hour,
+                session.clone(),
+            )
+        }
+    });
+
+    gui.on_add_allowed_app({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, app| {
+            gui_fn::restrictions::set_allowed_app(
+                gui_weak.clone(),
+                &user,
+                &app,
+                true,
+                session.clone(),
+            )
+        }
+    });
+
+    gui.on_remove_allowed_app({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |user, app| {
+            gui_fn::restrictions::set_allowed_app(
+                gui_weak.clone(),
+                &user,
+                &app,
+                false,
+                session.clone(),
+            )
         }
     });
 
+    gui.on_get_audit_log({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move |filter| {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui_fn::audit::get_audit_log(&gui, filter, &session)
+            }
+        }
+    });
+
+    let export_audit_log_timer = Rc::new(Timer::default());
+    gui.on_export_audit_log({
+        let gui_weak = gui_weak.clone();
+        let session = session.clone();
+        move || gui_fn::audit::export(gui_weak.clone(), &export_audit_log_timer, session.clone())
+    });
+
     // Virtual keyboard
     gui.global::<VirtualKeyboardHandler>().on_key_pressed({
         let gui_weak = gui_weak.clone();
@@ -223,6 +748,7 @@ fn main() -> Result<()> {
 fn quit(original_boot_config: &BootConfig, boot_config: Arc<Mutex<BootConfig>>) -> Result<()> {
     info!("Exiting");
 
+    autologin::validate(boot_config.clone());
     let mut final_boot_config = boot_config.lock().unwrap().clone();
     final_boot_config.flags.first_boot_done = true;
     if final_boot_config != *original_boot_config {
@@ -0,0 +1,316 @@
+use anyhow::{Context, Result};
+use libqinit::{OVERLAY_MOUNTPOINT, system};
+use log::{error, info, warn};
+use std::{fmt, fs, thread, time::Duration};
+
+use crate::{
+    groups, rekey,
+    users::{encrypted_storage_path, home_dir_path},
+};
+
+/// Login records of the system the user boots into
+pub const UTMP_FILE: &str = "/run/utmp";
+// struct utmp, as laid out by glibc and musl
+pub(crate) const UTMP_RECORD_SIZE: usize = 384;
+pub(crate) const UTMP_TYPE_OFFSET: usize = 0;
+const UTMP_PID_OFFSET: usize = 4;
+pub(crate) const UTMP_LINE_OFFSET: usize = 8;
+pub(crate) const UTMP_LINE_SIZE: usize = 32;
+pub(crate) const UTMP_USER_OFFSET: usize = 44;
+pub(crate) const UTMP_USER_SIZE: usize = 32;
+pub(crate) const USER_PROCESS: i16 = 7;
+// Time processes get to exit after SIGTERM before they are killed
+const TERMINATION_TIMEOUT: Duration = Duration::from_secs(5);
+const TERMINATION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub struct Process {
+    pub pid: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub pid: i32,
+    /// Terminal the user logged in on
+    pub line: String,
+}
+
+/// What keeps a user's storage busy
+#[derive(Clone, Debug, Default)]
+pub struct Activity {
+    pub mounts: Vec<String>,
+    pub processes: Vec<Process>,
+    pub sessions: Vec<Session>,
+}
+
+impl Activity {
+    pub fn is_idle(&self) -> bool {
+        self.mounts.is_empty() && self.processes.is_empty() && self.sessions.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct InUse {
+    pub user: String,
+    pub activity: Activity,
+}
+
+impl fmt::Display for InUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "User '{}' is in use: {} mount(s), {} running process(es) and {} session(s)",
+            &self.user,
+            self.activity.mounts.len(),
+            self.activity.processes.len(),
+            self.activity.sessions.len()
+        )
+    }
+}
+
+impl std::error::Error for InUse {}
+
+// Spaces and other special characters are octal-escaped in /proc/mounts
+pub(crate) fn unescape_mount_field(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+/// Mountpoints of the user's home directory, of anything below it, and of their storage elsewhere (like
+/// scratch mountpoints used while migrating it). Deepest first, so that they can be unmounted in order.
+fn mounts(user: &str) -> Result<Vec<String>> {
+    let home_dir_path = home_dir_path(&user);
+    let storage_path = encrypted_storage_path(&user);
+    let scratch_mountpoints: Vec<String> = rekey::SCRATCH_MOUNTPOINT_NAMES
+        .iter()
+        .map(|name| rekey::scratch_mountpoint(&user, &name))
+        .collect();
+    let contents = fs::read_to_string("/proc/mounts").with_context(|| "Failed to read mounts")?;
+
+    let mut mounts: Vec<String> = contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let source = unescape_mount_field(fields.next()?);
+            let mountpoint = unescape_mount_field(fields.next()?);
+            if mountpoint == home_dir_path
+                || mountpoint.starts_with(&format!("{}/", &home_dir_path))
+                || scratch_mountpoints.contains(&mountpoint)
+                || source == storage_path
+            {
+                Some(mountpoint)
+            } else {
+                None
+            }
+        })
+        .collect();
+    mounts.sort_by_key(|mountpoint| std::cmp::Reverse(mountpoint.matches('/').count()));
+    mounts.dedup();
+
+    Ok(mounts)
+}
+
+fn process_uid(pid: &str) -> Option<u32> {
+    let status = fs::read_to_string(&format!("/proc/{}/status", &pid)).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn processes(user: &str) -> Result<Vec<Process>> {
+    let Some(uid) = groups::user_id(&user)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut processes = Vec::new();
+    for entry in fs::read_dir("/proc").with_context(|| "Failed to list processes")? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        let Ok(pid) = name.parse::<i32>() else {
+            continue;
+        };
+        // Processes may exit while being looked at
+        if process_uid(&name) == Some(uid) {
+            processes.push(Process {
+                pid,
+                name: fs::read_to_string(&format!("/proc/{}/comm", &pid))
+                    .map(|comm| comm.trim().to_string())
+                    .unwrap_or_default(),
+            });
+        }
+    }
+
+    Ok(processes)
+}
+
+pub(crate) fn utmp_string(record: &[u8], offset: usize, size: usize) -> String {
+    let field = &record[offset..offset + size];
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(size);
+
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+pub fn parse_utmp(contents: &[u8]) -> Vec<(String, Session)> {
+    contents
+        .chunks_exact(UTMP_RECORD_SIZE)
+        .filter_map(|record| {
+            let record_type = i16::from_ne_bytes(
+                record[UTMP_TYPE_OFFSET..UTMP_TYPE_OFFSET + 2]
+                    .try_into()
+                    .ok()?,
+            );
+            if record_type != USER_PROCESS {
+                return None;
+            }
+            let pid = i32::from_ne_bytes(
+                record[UTMP_PID_OFFSET..UTMP_PID_OFFSET + 4]
+                    .try_into()
+                    .ok()?,
+            );
+
+            Some((
+                utmp_string(&record, UTMP_USER_OFFSET, UTMP_USER_SIZE),
+                Session {
+                    pid,
+                    line: utmp_string(&record, UTMP_LINE_OFFSET, UTMP_LINE_SIZE),
+                },
+            ))
+        })
+        .collect()
+}
+
+fn sessions(user: &str) -> Result<Vec<Session>> {
+    let path = format!("{}/{}", &OVERLAY_MOUNTPOINT, &UTMP_FILE);
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read(&path).with_context(|| "Failed to read login records")?;
+
+    // Records of sessions which did not end cleanly stay around
+    Ok(parse_utmp(&contents)
+        .into_iter()
+        .filter(|(session_user, session)| {
+            session_user == user && fs::exists(&format!("/proc/{}", session.pid)).unwrap_or(false)
+        })
+        .map(|(_, session)| session)
+        .collect())
+}
+
+pub fn check(user: &str) -> Result<Activity> {
+    Ok(Activity {
+        mounts: mounts(&user)?,
+        processes: processes(&user)?,
+        sessions: sessions(&user)?,
+    })
+}
+
+/// Fails with `InUse` if the user's storage is mounted, or if the user is running anything
+pub fn ensure_idle(user: &str) -> Result<()> {
+    let activity = check(&user)?;
+    if !activity.is_idle() {
+        return Err(InUse {
+            user: user.to_string(),
+            activity,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn signal_all(pids: &[i32], signal: libc::c_int) {
+    for pid in pids {
+        if unsafe { libc::kill(*pid, signal) } != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                warn!("Failed to signal process {}: {}", &pid, e);
+            }
+        }
+    }
+}
+
+fn running(pids: &[i32]) -> Vec<i32> {
+    pids.iter()
+        .copied()
+        .filter(|pid| fs::exists(&format!("/proc/{}", &pid)).unwrap_or(false))
+        .collect()
+}
+
+/// Ends the user's sessions and processes, then unmounts their storage
+pub fn release(user: &str) -> Result<()> {
+    let activity = check(&user)?;
+    info!(
+        "Releasing storage of user '{}': {} mount(s), {} process(es), {} session(s)",
+        &user,
+        activity.mounts.len(),
+        activity.processes.len(),
+        activity.sessions.len()
+    );
+
+    let mut pids: Vec<i32> = activity
+        .processes
+        .iter()
+        .map(|process| process.pid)
+        .chain(activity.sessions.iter().map(|session| session.pid))
+        .filter(|pid| *pid > 1)
+        .collect();
+    pids.sort();
+    pids.dedup();
+
+    signal_all(&pids, libc::SIGTERM);
+    let mut waited = Duration::ZERO;
+    while !running(&pids).is_empty() && waited < TERMINATION_TIMEOUT {
+        thread::sleep(TERMINATION_POLL_INTERVAL);
+        waited += TERMINATION_POLL_INTERVAL;
+    }
+    let remaining = running(&pids);
+    if !remaining.is_empty() {
+        warn!(
+            "Killing {} process(es) of user '{}' which did not exit",
+            remaining.len(),
+            &user
+        );
+        signal_all(&remaining, libc::SIGKILL);
+    }
+
+    for mountpoint in &activity.mounts {
+        if let Err(e) = system::run_command("/bin/umount", &[&mountpoint]) {
+            error!("Failed to unmount '{}': {}", &mountpoint, e);
+        }
+    }
+
+    ensure_idle(&user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written by glibc's updwtmpx() on x86_64: a boot, a login prompt, two sessions and an ended one
+    const UTMP: &[u8] = include_bytes!("../testdata/utmp");
+
+    #[test]
+    fn lists_user_processes_only() {
+        let sessions = parse_utmp(&UTMP);
+        let sessions: Vec<(&str, i32, &str)> = sessions
+            .iter()
+            .map(|(user, session)| (user.as_str(), session.pid, session.line.as_str()))
+            .collect();
+        assert_eq!(
+            sessions,
+            vec![("bob", 598, "tty1"), ("alice", 655, "pts/1")]
+        );
+    }
+
+    #[test]
+    fn ignores_truncated_records() {
+        assert_eq!(parse_utmp(&UTMP[..UTMP_RECORD_SIZE * 3 - 1]).len(), 0);
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use libqinit::OVERLAY_MOUNTPOINT;
+use log::{error, info, warn};
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+};
+
+use crate::time;
+
+pub const AUDIT_LOG_DIR: &str = "/var/log/core-settings";
+pub const AUDIT_LOG_FILE: &str = "audit.log";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    AdminLogin,
+    CreateUser,
+    DeleteUser,
+    SecureDeleteUser,
+    EndSessions,
+    GrantAdmin,
+    RevokeAdmin,
+    ChangePassword,
+    DisableEncryption,
+    SetDefaultUser,
+    CreateGroup,
+    DeleteGroup,
+    AddGroupMember,
+    RemoveGroupMember,
+    SetAdminGroup,
+    UnsetAdminGroup,
+    SetPrivilegeEscalation,
+    ResetPassword,
+    CreateRecoveryKey,
+    RecoverPassword,
+    UpgradeEncryption,
+    MigrateEncryption,
+    CheckStorage,
+    MoveDamagedFiles,
+    EncryptHome,
+    SetQuota,
+    BackupUser,
+    RestoreUser,
+    SetRestrictions,
+    RemoveRestrictions,
+    AdminPinLogin,
+    SetPin,
+    RemovePin,
+    SetAutoLogin,
+    MoveLoginScreenUser,
+    SetLoginScreenHidden,
+    SetDisplayName,
+}
+
+const ACTIONS: &[(Action, &str)] = &[
+    (Action::AdminLogin, "admin-login"),
+    (Action::CreateUser, "create-user"),
+    (Action::DeleteUser, "delete-user"),
+    (Action::SecureDeleteUser, "secure-delete-user"),
+    (Action::EndSessions, "end-sessions"),
+    (Action::GrantAdmin, "grant-admin"),
+    (Action::RevokeAdmin, "revoke-admin"),
+    (Action::ChangePassword, "change-password"),
+    (Action::DisableEncryption, "disable-encryption"),
+    (Action::SetDefaultUser, "set-default-user"),
+    (Action::CreateGroup, "create-group"),
+    (Action::DeleteGroup, "delete-group"),
+    (Action::AddGroupMember, "add-group-member"),
+    (Action::RemoveGroupMember, "remove-group-member"),
+    (Action::SetAdminGroup, "set-admin-group"),
+    (Action::UnsetAdminGroup, "unset-admin-group"),
+    (Action::SetPrivilegeEscalation, "set-privilege-escalation"),
+    (Action::ResetPassword, "reset-password"),
+    (Action::CreateRecoveryKey, "create-recovery-key"),
+    (Action::RecoverPassword, "recover-password"),
+    (Action::UpgradeEncryption, "upgrade-encryption"),
+    (Action::MigrateEncryption, "migrate-encryption"),
+    (Action::CheckStorage, "check-storage"),
+    (Action::MoveDamagedFiles, "move-damaged-files"),
+    (Action::EncryptHome, "encrypt-home"),
+    (Action::SetQuota, "set-quota"),
+    (Action::BackupUser, "backup-user"),
+    (Action::RestoreUser, "restore-user"),
+    (Action::SetRestrictions, "set-restrictions"),
+    (Action::RemoveRestrictions, "remove-restrictions"),
+    (Action::AdminPinLogin, "admin-pin-login"),
+    (Action::SetPin, "set-pin"),
+    (Action::RemovePin, "remove-pin"),
+    (Action::SetAutoLogin, "set-auto-login"),
+    (Action::MoveLoginScreenUser, "move-login-screen-user"),
+    (Action::SetLoginScreenHidden, "set-login-screen-hidden"),
+    (Action::SetDisplayName, "set-display-name"),
+];
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(action, _)| action == self)
+            .map(|(_, name)| *name)
+            .unwrap_or("unknown")
+    }
+
+    fn from_str(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(_, entry)| *entry == name)
+            .map(|(action, _)| *action)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub timestamp: u64,
+    pub actor: String,
+    pub action: Action,
+    /// User or group the action applied to
+    pub target: String,
+    pub success: bool,
+}
+
+fn audit_log_dir_path() -> String {
+    format!("{}/{}", &OVERLAY_MOUNTPOINT, &AUDIT_LOG_DIR)
+}
+
+fn audit_log_path() -> String {
+    format!("{}/{}", &audit_log_dir_path(), &AUDIT_LOG_FILE)
+}
+
+// Fields are tab-separated, one entry per line
+fn sanitize(field: &str) -> String {
+    field
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+fn append(entry: &Entry) -> Result<()> {
+    fs::create_dir_all(&audit_log_dir_path())
+        .with_context(|| "Failed to create audit log directory")?;
+    fs::set_permissions(&audit_log_dir_path(), fs::Permissions::from_mode(0o700))?;
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(&audit_log_path())
+        .with_context(|| "Failed to open audit log")?;
+    writeln!(
+        file,
+        "{}\t{}\t{}\t{}\t{}",
+        &entry.timestamp,
+        &sanitize(&entry.actor),
+        &entry.action.as_str(),
+        &sanitize(&entry.target),
+        if entry.success { "success" } else { "failure" }
+    )
+    .with_context(|| "Failed to write to audit log")?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Only records who did what to whom: there is deliberately no room for free-form details, so that secrets
+/// (or error messages containing them) never end up in the log
+pub fn record(actor: &str, action: Action, target: &str, success: bool) {
+    let entry = Entry {
+        timestamp: time::now(),
+        actor: actor.to_string(),
+        action,
+        target: target.to_string(),
+        success,
+    };
+    info!("Audit: {:?}", &entry);
+
+    if let Err(e) = append(&entry) {
+        error!("Failed to record audit log entry: {}", e);
+    }
+}
+
+pub fn read() -> Result<Vec<Entry>> {
+    let path = audit_log_path();
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(&path).with_context(|| "Failed to read audit log")?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let entry = match fields.as_slice() {
+                [timestamp, actor, action, target, outcome] => Some(Entry {
+                    timestamp: timestamp.parse().ok()?,
+                    actor: actor.to_string(),
+                    action: Action::from_str(&action)?,
+                    target: target.to_string(),
+                    success: *outcome == "success",
+                }),
+                _ => None,
+            };
+            if entry.is_none() {
+                warn!("Ignoring malformed audit log line '{}'", &line);
+            }
+
+            entry
+        })
+        .collect())
+}
+
+/// Case-insensitive match on actor, action, target or outcome
+pub fn matches(entry: &Entry, filter: &str) -> bool {
+    let filter = filter.trim().to_lowercase();
+    if filter.is_empty() {
+        return true;
+    }
+
+    [
+        entry.actor.as_str(),
+        entry.action.as_str(),
+        entry.target.as_str(),
+        if entry.success { "success" } else { "failure" },
+    ]
+    .iter()
+    .any(|field| field.to_lowercase().contains(&filter))
+}
+
+/// Writes a human-readable copy of the audit log into the given directory and returns its path
+pub fn export(destination_dir: &str) -> Result<String> {
+    let mut contents = String::from("Time (UTC)\tActor\tAction\tTarget\tOutcome\n");
+    for entry in read()? {
+        contents.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            &time::format_timestamp(entry.timestamp),
+            &entry.actor,
+            &entry.action.as_str(),
+            &entry.target,
+            if entry.success { "success" } else { "failure" }
+        ));
+    }
+
+    let path = format!(
+        "{}/core-settings-audit-{}.tsv",
+        &destination_dir,
+        &time::now()
+    );
+    fs::write(&path, &contents)
+        .with_context(|| format!("Failed to export audit log to '{}'", &path))?;
+    info!("Exported audit log to '{}'", &path);
+
+    Ok(path)
+}
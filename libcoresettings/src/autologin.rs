@@ -0,0 +1,68 @@
+use anyhow::Result;
+use libqinit::boot_config::BootConfig;
+use log::{info, warn};
+use std::sync::{Arc, Mutex};
+
+use crate::encryption;
+
+/// Auto-login skips the password prompt at boot, so the user's storage has to be unlockable without one: in
+/// disabled mode, the password is known.
+pub fn check_eligible(user: &str) -> Result<()> {
+    if encryption::status(&user)?.enabled {
+        return Err(anyhow::anyhow!(
+            "User '{}' has encrypted storage: disable encryption to allow auto-login",
+            &user
+        ));
+    }
+
+    Ok(())
+}
+
+/// Auto-login always applies to the default user
+pub fn set(enabled: bool, boot_config: Arc<Mutex<BootConfig>>) -> Result<()> {
+    let mut boot_config = boot_config.lock().unwrap();
+    if enabled {
+        let Some(user) = boot_config.system.default_user.clone() else {
+            return Err(anyhow::anyhow!("Auto-login requires a default user"));
+        };
+        check_eligible(&user)?;
+        info!("Enabling auto-login of user '{}'", &user);
+    } else {
+        info!("Disabling auto-login");
+    }
+    boot_config.system.auto_login = enabled;
+
+    Ok(())
+}
+
+/// The default user being deleted, nobody is left to log in automatically
+pub fn remove(user: &str, boot_config: Arc<Mutex<BootConfig>>) {
+    let mut boot_config = boot_config.lock().unwrap();
+    if boot_config.system.default_user.as_deref() == Some(user) {
+        if boot_config.system.auto_login {
+            info!("Disabling auto-login of deleted user '{}'", &user);
+        }
+        boot_config.system.default_user = None;
+        boot_config.system.auto_login = false;
+    }
+}
+
+/// Turns auto-login off if the default user changed in a way that no longer allows it. Returns whether it did.
+pub fn validate(boot_config: Arc<Mutex<BootConfig>>) -> bool {
+    let mut boot_config = boot_config.lock().unwrap();
+    if !boot_config.system.auto_login {
+        return false;
+    }
+
+    let result = match boot_config.system.default_user.clone() {
+        Some(user) => check_eligible(&user),
+        None => Err(anyhow::anyhow!("No default user")),
+    };
+    if let Err(e) = result {
+        warn!("Disabling auto-login: {}", e);
+        boot_config.system.auto_login = false;
+        return true;
+    }
+
+    false
+}
@@ -0,0 +1,292 @@
+use anyhow::{Context, Result};
+use libqinit::{boot_config::BootConfig, storage_encryption, system};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Read,
+    os::unix::fs::lchown,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    activity, escrow, gocryptfs, groups, removable, time,
+    users::{self, encrypted_storage_path, home_dir_path},
+};
+
+pub const BACKUP_FILE_PREFIX: &str = "core-settings-backup-";
+pub const BACKUP_FILE_EXTENSION: &str = "tar";
+const MANIFEST_FILE: &str = "manifest.json";
+// Directory of the archive holding the encrypted storage, as is
+const STORAGE_DIR: &str = "storage";
+const FORMAT_VERSION: u32 = 1;
+
+/// Account metadata stored next to the encrypted storage
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub user: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// Only informative: rights are not restored from archives, which anyone may have tampered with
+    pub admin: bool,
+    /// Supplementary groups, informative as well
+    pub groups: Vec<String>,
+    pub encryption_disabled: bool,
+    pub created: u64,
+}
+
+fn manifest_for(user: &str) -> Result<Manifest> {
+    let storage_path = encrypted_storage_path(&user);
+    let groups = groups::list()?
+        .into_iter()
+        .filter(|group| group.members.iter().any(|member| member == user))
+        .map(|group| group.name)
+        .collect();
+
+    Ok(Manifest {
+        version: FORMAT_VERSION,
+        user: user.to_string(),
+        uid: groups::user_id(&user)?
+            .ok_or_else(|| anyhow::anyhow!("User '{}' does not exist", &user))?,
+        gid: groups::primary_group_id(&user)?
+            .ok_or_else(|| anyhow::anyhow!("User '{}' has no primary group", &user))?,
+        admin: users::is_admin(&user),
+        groups,
+        encryption_disabled: fs::exists(&format!(
+            "{}/{}",
+            &storage_path,
+            &storage_encryption::DISABLED_MODE_FILE
+        ))?,
+        created: time::now(),
+    })
+}
+
+/// Writes a user's account metadata and encrypted storage into a single archive in `dir`. Data is never
+/// decrypted: the archive is as safe as the user's password.
+pub fn export(user: &str, dir: &str) -> Result<String> {
+    info!("Backing up user '{}' to '{}'", &user, &dir);
+    activity::ensure_idle(&user)?;
+
+    let manifest = manifest_for(&user)?;
+    let manifest_contents = serde_json::to_vec_pretty(&manifest)?;
+    let path = format!(
+        "{}/{}{}-{}.{}",
+        &dir, &BACKUP_FILE_PREFIX, &user, manifest.created, &BACKUP_FILE_EXTENSION
+    );
+    let temporary_path = format!("{}.part", &path);
+
+    let result = (|| -> Result<()> {
+        let file = fs::File::create(&temporary_path)
+            .with_context(|| format!("Failed to create '{}'", &temporary_path))?;
+        let mut builder = tar::Builder::new(file);
+        builder.follow_symlinks(false);
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_contents.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(manifest.created);
+        header.set_cksum();
+        builder.append_data(&mut header, &MANIFEST_FILE, manifest_contents.as_slice())?;
+        builder
+            .append_dir_all(&STORAGE_DIR, &encrypted_storage_path(&user))
+            .with_context(|| "Failed to archive encrypted storage")?;
+
+        builder.into_inner()?.sync_all()?;
+        fs::rename(&temporary_path, &path)?;
+
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temporary_path);
+        return Err(e).with_context(|| "Failed to write backup");
+    }
+    info!("Backed up user '{}' to '{}'", &user, &path);
+
+    Ok(path)
+}
+
+/// Backups found in a directory, most recent first
+pub fn list(dir: &str) -> Result<Vec<String>> {
+    let mut backups: Vec<String> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read directory '{}'", &dir))?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().to_string_lossy().to_string();
+            if name.starts_with(&BACKUP_FILE_PREFIX)
+                && name.ends_with(&format!(".{}", &BACKUP_FILE_EXTENSION))
+            {
+                Some(format!("{}/{}", &dir, &name))
+            } else {
+                None
+            }
+        })
+        .collect();
+    backups.sort_by_key(|path| read_manifest(&path).map(|manifest| manifest.created).ok());
+    backups.reverse();
+
+    Ok(backups)
+}
+
+/// Backups found on all mounted removable storage devices
+pub fn list_removable() -> Result<Vec<String>> {
+    let mut backups = Vec::new();
+    for mountpoint in removable::mountpoints()? {
+        match list(&mountpoint) {
+            Ok(found) => backups.extend(found),
+            Err(e) => warn!("Failed to look for backups in '{}': {}", &mountpoint, e),
+        }
+    }
+
+    Ok(backups)
+}
+
+pub fn read_manifest(archive_path: &str) -> Result<Manifest> {
+    let file = fs::File::open(&archive_path)
+        .with_context(|| format!("Failed to open '{}'", &archive_path))?;
+    let mut archive = tar::Archive::new(file);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_ref() == Path::new(&MANIFEST_FILE) {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            let manifest: Manifest =
+                serde_json::from_str(&contents).with_context(|| "Invalid backup manifest")?;
+            if manifest.version > FORMAT_VERSION {
+                return Err(anyhow::anyhow!(
+                    "Backup format version {} is not supported",
+                    manifest.version
+                ));
+            }
+
+            return Ok(manifest);
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "'{}' is not a Core Settings backup",
+        &archive_path
+    ))
+}
+
+// The storage belongs to the restored user only, whatever IDs the archive claims
+fn chown_tree(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    lchown(&path, Some(uid), Some(gid))?;
+    if fs::symlink_metadata(&path)?.is_dir() {
+        for entry in fs::read_dir(&path)? {
+            chown_tree(&entry?.path(), uid, gid)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Backups may come from anywhere: ownership, setuid/setgid bits and extended attributes are not taken from
+// them, and only the kinds of entries `export` writes are accepted
+fn unpack(archive_path: &str, restore_path: &str) -> Result<()> {
+    let mut archive = tar::Archive::new(fs::File::open(&archive_path)?);
+    archive.set_preserve_permissions(false);
+    archive.set_preserve_ownerships(false);
+    archive.set_unpack_xattrs(false);
+    for entry in archive.entries().with_context(|| "Failed to read backup")? {
+        let mut entry = entry.with_context(|| "Failed to read backup")?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() && !entry_type.is_symlink() {
+            return Err(anyhow::anyhow!(
+                "Backup contains unsupported entry '{}'",
+                entry.path()?.display()
+            ));
+        }
+        entry
+            .unpack_in(&restore_path)
+            .with_context(|| "Failed to extract backup")?;
+    }
+
+    Ok(())
+}
+
+fn restore_storage(archive_path: &str, manifest: &Manifest, password: &str) -> Result<()> {
+    let user = &manifest.user;
+    let storage_path = encrypted_storage_path(&user);
+    let restore_path = format!("{}.restore", &storage_path);
+    if fs::exists(&restore_path)? {
+        system::rm_dir_all(&restore_path)
+            .with_context(|| "Failed to remove leftovers of a previous restore")?;
+    }
+    fs::create_dir_all(&restore_path)?;
+
+    let result = (|| -> Result<()> {
+        unpack(&archive_path, &restore_path)?;
+
+        let restored_storage_path = format!("{}/{}", &restore_path, &STORAGE_DIR);
+        gocryptfs::dump_master_key(&restored_storage_path, &password)
+            .with_context(|| "Password does not unlock the backup")?;
+
+        let uid = groups::user_id(&user)?
+            .ok_or_else(|| anyhow::anyhow!("User '{}' does not exist", &user))?;
+        let gid = groups::primary_group_id(&user)?
+            .ok_or_else(|| anyhow::anyhow!("User '{}' has no primary group", &user))?;
+        chown_tree(Path::new(&restored_storage_path), uid, gid)
+            .with_context(|| "Failed to set filesystem permissions")?;
+
+        fs::rename(&restored_storage_path, &storage_path)
+            .with_context(|| "Failed to move restored storage in place")?;
+        fs::create_dir_all(&home_dir_path(&user))?;
+
+        Ok(())
+    })();
+    let _ = system::rm_dir_all(&restore_path);
+
+    result
+}
+
+/// Recreates a user from a backup. The storage is restored as is, so the password it was backed up with
+/// still unlocks it, and becomes the account's password. Administrator rights and group memberships recorded
+/// in the archive are ignored: the user only becomes an administrator with `make_admin`, and has to be added
+/// to groups again.
+pub fn import(
+    archive_path: &str,
+    password: &str,
+    make_admin: bool,
+    make_default: bool,
+    boot_config: Arc<Mutex<BootConfig>>,
+) -> Result<String> {
+    let manifest = read_manifest(&archive_path)?;
+    let user = &manifest.user;
+    info!("Restoring user '{}' from '{}'", &user, &archive_path);
+    if groups::user_id(&user)?.is_some() || fs::exists(&encrypted_storage_path(&user))? {
+        return Err(anyhow::anyhow!("User '{}' already exists", &user));
+    }
+    // Storage in disabled mode uses a known password: the one given only becomes the account's
+    let storage_password = if manifest.encryption_disabled {
+        storage_encryption::DISABLED_MODE_PASSWORD
+    } else {
+        password
+    };
+
+    // Granted last, so that a failed restore can still delete the account if it would be the only administrator
+    users::create_account(&user, &password, false, None)?;
+    if let Err(e) = restore_storage(&archive_path, &manifest, &storage_password) {
+        if let Err(e) = users::delete(&user, boot_config.clone()) {
+            error!("Failed to remove partially restored user: {}", &e);
+        }
+        return Err(e);
+    }
+
+    if make_admin {
+        users::change_admin_status(&user, true)
+            .with_context(|| "Failed to grant administrator rights")?;
+    }
+    if !manifest.encryption_disabled {
+        if let Err(e) = escrow::escrow(&user, &encrypted_storage_path(&user), &password) {
+            error!("Failed to escrow key of user '{}': {}", &user, e);
+        }
+    }
+    if make_default {
+        users::set_default_user(&user, boot_config)
+            .with_context(|| "Failed to set default user")?;
+    }
+    info!("Restored user '{}'", &user);
+
+    Ok(user.to_string())
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use libcoresettings::{
+    audit::{self, Action},
+    fsck,
+};
+use std::io::BufRead;
+
+const USAGE: &str = "Usage: core-settings-cli <command> [arguments]
+
+Commands:
+    fsck <user> [--move-aside]    Check a user's encrypted storage, optionally moving damaged files aside
+
+The user's password is read from standard input.";
+
+fn read_password() -> Result<String> {
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .with_context(|| "Failed to read password from standard input")?;
+
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn print_report(report: &fsck::Report) {
+    for problem in &report.problems {
+        println!("{}", &problem.message);
+    }
+    if !report.summary.is_empty() {
+        println!("Summary: {}", &report.summary);
+    }
+}
+
+/// Returns whether the storage is clean, or was made usable
+fn fsck_command(args: &[String]) -> Result<bool> {
+    let (user, move_aside) = match args {
+        [user] => (user, false),
+        [user, flag] if flag == "--move-aside" => (user, true),
+        _ => return Err(anyhow::anyhow!("{}", &USAGE)),
+    };
+    let password = read_password()?;
+
+    if move_aside {
+        let result = fsck::move_damaged_files(&user, &password);
+        // A password proves the caller is the user
+        audit::record(&user, Action::MoveDamagedFiles, &user, result.is_ok());
+        let (report, moved) = result?;
+        print_report(&report);
+        for file in &moved {
+            println!("Moved '{}' to '{}'", &file, &fsck::DAMAGED_FILES_DIR);
+        }
+
+        Ok(moved.len() == report.damaged_files().len())
+    } else {
+        let result = fsck::check(&user, &password);
+        audit::record(&user, Action::CheckStorage, &user, result.is_ok());
+        let report = result?;
+        print_report(&report);
+
+        Ok(report.is_clean())
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    // Users' storage and Core Settings' state are only accessible to root, and audit records are attributed
+    // assuming so
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("Error: core-settings-cli has to be run as root");
+        std::process::exit(2);
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("fsck") => fsck_command(&args[1..]),
+        _ => {
+            eprintln!("{}", &USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match result {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use libqinit::OVERLAY_MOUNTPOINT;
+use log::{info, warn};
+use std::{fs, os::unix::fs::PermissionsExt, time::Duration};
+
+pub const CONFIG_FILE: &str = "/etc/core-settings.conf";
+pub const STATE_DIR: &str = "/var/lib/core-settings";
+const DEFAULT_ADMIN_GROUP: &str = "wheel";
+const DEFAULT_ADMIN_IDLE_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_STALE_ACCOUNT_DAYS: u64 = 90;
+const DEFAULT_PASSWORD_HISTORY_LENGTH: usize = 5;
+const SECONDS_PER_DAY: u64 = 86400;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrivilegeEscalation {
+    None,
+    Sudo,
+    Doas,
+}
+
+impl PrivilegeEscalation {
+    fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Self::None),
+            "sudo" => Some(Self::Sudo),
+            "doas" => Some(Self::Doas),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Sudo => "sudo",
+            Self::Doas => "doas",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The first group is the one new administrators are added to
+    pub admin_groups: Vec<String>,
+    pub privilege_escalation: PrivilegeEscalation,
+    pub admin_idle_timeout: Duration,
+    /// Time without logging in after which an account is reported as stale
+    pub stale_account_age: Duration,
+    /// Number of previous passwords a new password cannot be one of. Zero allows reusing passwords.
+    pub password_history_length: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            admin_groups: vec![DEFAULT_ADMIN_GROUP.to_string()],
+            privilege_escalation: PrivilegeEscalation::None,
+            admin_idle_timeout: Duration::from_secs(DEFAULT_ADMIN_IDLE_TIMEOUT_SECS),
+            stale_account_age: Duration::from_secs(DEFAULT_STALE_ACCOUNT_DAYS * SECONDS_PER_DAY),
+            password_history_length: DEFAULT_PASSWORD_HISTORY_LENGTH,
+        }
+    }
+}
+
+fn config_file_path() -> String {
+    format!("{}/{}", &OVERLAY_MOUNTPOINT, &CONFIG_FILE)
+}
+
+/// Path of a file or directory persisted by Core Settings in the overlay filesystem
+pub fn state_path(name: &str) -> String {
+    format!("{}/{}/{}", &OVERLAY_MOUNTPOINT, &STATE_DIR, &name)
+}
+
+pub fn create_state_dir() -> Result<()> {
+    let path = format!("{}/{}", &OVERLAY_MOUNTPOINT, &STATE_DIR);
+    fs::create_dir_all(&path).with_context(|| "Failed to create Core Settings state directory")?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o700))?;
+
+    Ok(())
+}
+
+impl Config {
+    pub fn read() -> Result<Self> {
+        let mut config = Self::default();
+        let path = config_file_path();
+        if !fs::exists(&path)? {
+            return Ok(config);
+        }
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| "Failed to read Core Settings configuration file")?;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                warn!("Ignoring malformed configuration line '{}'", &line);
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "admin_groups" => {
+                    let admin_groups: Vec<String> = value
+                        .split(',')
+                        .map(|group| group.trim().to_string())
+                        .filter(|group| !group.is_empty())
+                        .collect();
+                    if !admin_groups.is_empty() {
+                        config.admin_groups = admin_groups;
+                    }
+                }
+                "privilege_escalation" => {
+                    if let Some(privilege_escalation) = PrivilegeEscalation::from_str(&value) {
+                        config.privilege_escalation = privilege_escalation;
+                    } else {
+                        warn!("Unknown privilege escalation method '{}'", &value);
+                    }
+                }
+                "admin_idle_timeout_secs" => match value.parse::<u64>() {
+                    Ok(secs) if secs > 0 => config.admin_idle_timeout = Duration::from_secs(secs),
+                    _ => warn!("Invalid administrator idle timeout '{}'", &value),
+                },
+                "stale_account_days" => match value.parse::<u64>() {
+                    Ok(days) if days > 0 => {
+                        config.stale_account_age = Duration::from_secs(days * SECONDS_PER_DAY)
+                    }
+                    _ => warn!("Invalid stale account age '{}'", &value),
+                },
+                "password_history_length" => match value.parse::<usize>() {
+                    Ok(length) => config.password_history_length = length,
+                    _ => warn!("Invalid password history length '{}'", &value),
+                },
+                _ => warn!("Ignoring unknown configuration key '{}'", &key),
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        info!("Writing Core Settings configuration: {:?}", &self);
+        let mut contents = String::from("# Managed by Core Settings\n");
+        contents.push_str(&format!(
+            "admin_groups = {}\n",
+            &self.admin_groups.join(",")
+        ));
+        contents.push_str(&format!(
+            "privilege_escalation = {}\n",
+            &self.privilege_escalation.as_str()
+        ));
+        contents.push_str(&format!(
+            "admin_idle_timeout_secs = {}\n",
+            &self.admin_idle_timeout.as_secs()
+        ));
+        contents.push_str(&format!(
+            "stale_account_days = {}\n",
+            self.stale_account_age.as_secs() / SECONDS_PER_DAY
+        ));
+        contents.push_str(&format!(
+            "password_history_length = {}\n",
+            &self.password_history_length
+        ));
+        fs::write(&config_file_path(), &contents)
+            .with_context(|| "Failed to write Core Settings configuration file")?;
+
+        Ok(())
+    }
+
+    pub fn primary_admin_group(&self) -> &str {
+        self.admin_groups
+            .first()
+            .map(|group| group.as_str())
+            .unwrap_or(DEFAULT_ADMIN_GROUP)
+    }
+}
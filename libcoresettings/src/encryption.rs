@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use libqinit::{storage_encryption, system};
+use std::fs;
+
+use crate::{
+    gocryptfs::{self, run_with_stdin},
+    users::{encrypted_storage_path, home_dir_path},
+    wipe,
+};
+
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    /// Whether the user's home directory is currently accessible
+    pub unlocked: bool,
+}
+
+/// Sets up an empty gocryptfs volume at `.<user>`, next to the home directory, protected with the given password
+pub fn init(user: &str, password: &str) -> Result<()> {
+    gocryptfs::init(&encrypted_storage_path(&user), &password, None)
+}
+
+pub fn mount(user: &str, password: &str) -> Result<()> {
+    storage_encryption::mount_storage(&user, &password)
+}
+
+pub fn unmount(user: &str) -> Result<()> {
+    storage_encryption::unmount_storage(&user)
+}
+
+pub fn change_password(user: &str, old_password: &str, new_password: &str) -> Result<()> {
+    let encrypted_storage_path = encrypted_storage_path(&user);
+    run_with_stdin(
+        &storage_encryption::GOCRYPTFS_BINARY,
+        &["-passwd", &encrypted_storage_path],
+        &format!("{}\n{}\n", &old_password, &new_password),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to change encrypted storage's password for user '{}'",
+            &user
+        )
+    })?;
+
+    let encryption_disabled_file_path = format!(
+        "{}/{}",
+        &encrypted_storage_path,
+        &storage_encryption::DISABLED_MODE_FILE
+    );
+    if new_password != storage_encryption::DISABLED_MODE_PASSWORD
+        && fs::exists(&encryption_disabled_file_path)?
+    {
+        fs::remove_file(&encryption_disabled_file_path)?;
+    }
+
+    Ok(())
+}
+
+/// Data stays encrypted, under a password known to the system
+pub fn disable(user: &str, password: &str) -> Result<()> {
+    change_password(
+        &user,
+        &password,
+        &storage_encryption::DISABLED_MODE_PASSWORD,
+    )?;
+    fs::File::create(&format!(
+        "{}/{}",
+        &encrypted_storage_path(&user),
+        &storage_encryption::DISABLED_MODE_FILE,
+    ))
+    .with_context(|| {
+        format!(
+            "Failed to create file disabling encryption for user '{}'",
+            &user
+        )
+    })?;
+
+    Ok(())
+}
+
+pub fn status(user: &str) -> Result<EncryptionStatus> {
+    Ok(EncryptionStatus {
+        enabled: storage_encryption::get_encryption_user_details(&user)?.encryption_enabled,
+        unlocked: system::is_mountpoint(&home_dir_path(&user))?,
+    })
+}
+
+/// Securely destroys the configuration file, which holds the master key wrapped with the password, making the
+/// storage unreadable. Backups left by password changes hold it as well, wrapped with previous passwords.
+/// Returns a description of what was destroyed.
+pub fn destroy_keys(user: &str) -> Result<Vec<String>> {
+    let encrypted_storage_path = encrypted_storage_path(&user);
+    if !fs::exists(&encrypted_storage_path)? {
+        return Ok(Vec::new());
+    }
+
+    let mut destroyed = Vec::new();
+    for entry in fs::read_dir(&encrypted_storage_path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(&gocryptfs::CONFIG_FILE) || !entry.file_type()?.is_file() {
+            continue;
+        }
+        wipe::overwrite_and_remove(&format!("{}/{}", &encrypted_storage_path, &name))?;
+        destroyed.push(format!("gocryptfs configuration ({})", &name));
+    }
+
+    Ok(destroyed)
+}
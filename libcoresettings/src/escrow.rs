@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use libqinit::OVERLAY_MOUNTPOINT;
+use log::info;
+use openssl::{
+    base64,
+    encrypt::{Decrypter, Encrypter},
+    hash::MessageDigest,
+    pkey::PKey,
+    rsa::{Padding, Rsa},
+    symm::Cipher,
+};
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use crate::{config, gocryptfs, wipe};
+
+/// Escrow is only enabled once this public key is installed
+pub const RECOVERY_PUBLIC_KEY_FILE: &str = "/etc/core-settings/recovery.pub.pem";
+/// The matching private key never lives on the device: it is kept on removable storage by administrators
+pub const RECOVERY_PRIVATE_KEY_FILE_NAME: &str = "core-settings-recovery.pem";
+const ESCROW_DIR: &str = "escrow";
+const RECOVERY_KEY_BITS: u32 = 4096;
+
+fn recovery_public_key_path() -> String {
+    format!("{}/{}", &OVERLAY_MOUNTPOINT, &RECOVERY_PUBLIC_KEY_FILE)
+}
+
+fn escrow_path(user: &str) -> String {
+    format!("{}/{}", &config::state_path(&ESCROW_DIR), &user)
+}
+
+pub fn is_enabled() -> bool {
+    fs::exists(&recovery_public_key_path()).unwrap_or(false)
+}
+
+pub fn is_escrowed(user: &str) -> bool {
+    fs::exists(&escrow_path(&user)).unwrap_or(false)
+}
+
+/// Stores the master key of a user's encrypted storage, encrypted to the recovery public key. Does nothing if
+/// escrow is not enabled.
+pub fn escrow(user: &str, encrypted_storage_path: &str, password: &str) -> Result<()> {
+    if !is_enabled() {
+        info!(
+            "Key escrow is not enabled: not escrowing key of user '{}'",
+            &user
+        );
+        return Ok(());
+    }
+
+    let public_key = PKey::public_key_from_pem(
+        &fs::read(&recovery_public_key_path())
+            .with_context(|| "Failed to read recovery public key")?,
+    )?;
+    let master_key = gocryptfs::dump_master_key(&encrypted_storage_path, &password)?;
+
+    let mut encrypter = Encrypter::new(&public_key)?;
+    encrypter.set_rsa_padding(Padding::PKCS1_OAEP)?;
+    encrypter.set_rsa_oaep_md(MessageDigest::sha256())?;
+    let mut escrowed_key = vec![0; encrypter.encrypt_len(&master_key)?];
+    let length = encrypter.encrypt(&master_key, &mut escrowed_key)?;
+    escrowed_key.truncate(length);
+
+    config::create_state_dir()?;
+    let escrow_dir_path = config::state_path(&ESCROW_DIR);
+    fs::create_dir_all(&escrow_dir_path)?;
+    fs::set_permissions(&escrow_dir_path, fs::Permissions::from_mode(0o700))?;
+    let path = escrow_path(&user);
+    fs::write(&path, &base64::encode_block(&escrowed_key))
+        .with_context(|| format!("Failed to write escrowed key of user '{}'", &user))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    info!("Escrowed encrypted storage key of user '{}'", &user);
+
+    Ok(())
+}
+
+pub fn remove(user: &str) -> Result<()> {
+    let path = escrow_path(&user);
+    if fs::exists(&path)? {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove escrowed key of user '{}'", &user))?;
+    }
+
+    Ok(())
+}
+
+/// Like `remove`, but overwrites the escrowed key first. Returns whether there was one.
+pub fn destroy(user: &str) -> Result<bool> {
+    let path = escrow_path(&user);
+    if !fs::exists(&path)? {
+        return Ok(false);
+    }
+    wipe::overwrite_and_remove(&path)
+        .with_context(|| format!("Failed to destroy escrowed key of user '{}'", &user))?;
+
+    Ok(true)
+}
+
+/// Generates the recovery key pair: the public key is installed, and the private key, protected by the given
+/// passphrase, is written into the given directory. Returns the private key's path.
+pub fn create_recovery_key(destination_dir: &str, passphrase: &str) -> Result<String> {
+    if is_enabled() {
+        return Err(anyhow::anyhow!(
+            "A recovery key is already set up: existing escrowed keys depend on it"
+        ));
+    }
+
+    let key = PKey::from_rsa(Rsa::generate(RECOVERY_KEY_BITS)?)?;
+    let private_key_path = format!("{}/{}", &destination_dir, &RECOVERY_PRIVATE_KEY_FILE_NAME);
+    fs::write(
+        &private_key_path,
+        &key.private_key_to_pem_pkcs8_passphrase(Cipher::aes_256_cbc(), passphrase.as_bytes())?,
+    )
+    .with_context(|| {
+        format!(
+            "Failed to write recovery private key to '{}'",
+            &private_key_path
+        )
+    })?;
+
+    let public_key_path = recovery_public_key_path();
+    if let Some(parent) = std::path::Path::new(&public_key_path).parent() {
+        fs::create_dir_all(&parent)?;
+    }
+    fs::write(&public_key_path, &key.public_key_to_pem()?)
+        .with_context(|| "Failed to install recovery public key")?;
+    info!(
+        "Created recovery key, private key written to '{}'",
+        &private_key_path
+    );
+
+    Ok(private_key_path)
+}
+
+fn recover_master_key(user: &str, private_key_path: &str, passphrase: &str) -> Result<Vec<u8>> {
+    let escrowed_key = base64::decode_block(
+        fs::read_to_string(&escrow_path(&user))
+            .with_context(|| format!("No escrowed key found for user '{}'", &user))?
+            .trim(),
+    )?;
+    let private_key = PKey::private_key_from_pem_passphrase(
+        &fs::read(&private_key_path).with_context(|| {
+            format!(
+                "Failed to read recovery private key at '{}'",
+                &private_key_path
+            )
+        })?,
+        passphrase.as_bytes(),
+    )
+    .with_context(|| "Failed to unlock recovery private key")?;
+
+    let mut decrypter = Decrypter::new(&private_key)?;
+    decrypter.set_rsa_padding(Padding::PKCS1_OAEP)?;
+    decrypter.set_rsa_oaep_md(MessageDigest::sha256())?;
+    let mut master_key = vec![0; decrypter.decrypt_len(&escrowed_key)?];
+    let length = decrypter
+        .decrypt(&escrowed_key, &mut master_key)
+        .with_context(|| "Escrowed key does not match recovery private key")?;
+    master_key.truncate(length);
+
+    Ok(master_key)
+}
+
+/// Re-wraps a user's encrypted storage with a new password using its escrowed master key
+pub fn reset_encryption_password(
+    user: &str,
+    encrypted_storage_path: &str,
+    private_key_path: &str,
+    passphrase: &str,
+    new_password: &str,
+) -> Result<()> {
+    let master_key = recover_master_key(&user, &private_key_path, &passphrase)?;
+    // A backup left by an earlier interrupted change would make gocryptfs refuse to run
+    gocryptfs::remove_config_backup(&encrypted_storage_path)?;
+    gocryptfs::set_password_with_master_key(&encrypted_storage_path, &master_key, &new_password)
+        .with_context(|| {
+            format!(
+                "Failed to reset encrypted storage's password for user '{}'",
+                &user
+            )
+        })?;
+    gocryptfs::remove_config_backup(&encrypted_storage_path)?;
+    info!(
+        "Reset encrypted storage password of user '{}' using escrowed key",
+        &user
+    );
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use anyhow::{Context, Result};
+use log::{error, info};
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, chown},
+    path::{Component, Path},
+};
+
+use crate::{activity, gocryptfs, rekey, users::encrypted_storage_path};
+
+/// Directory at the root of the user's home where unreadable files are moved to
+pub const DAMAGED_FILES_DIR: &str = "Damaged files";
+// Problems gocryptfs reports about a file whose name is readable but contents are not
+const UNREADABLE_FILE_PREFIXES: &[&str] = &[
+    "error opening file",
+    "error reading file",
+    "corrupt file",
+    "error reading symlink",
+];
+
+#[derive(Clone, Debug)]
+pub struct Problem {
+    /// Path relative to the home directory, when gocryptfs reports one
+    pub path: Option<String>,
+    pub message: String,
+    pub unreadable_file: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+    pub summary: String,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Files which can be moved aside by path
+    pub fn damaged_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = Vec::new();
+        for problem in &self.problems {
+            if let Some(path) = &problem.path {
+                if problem.unreadable_file && !files.contains(path) {
+                    files.push(path.to_string());
+                }
+            }
+        }
+
+        files
+    }
+}
+
+// gocryptfs quotes paths Go-style
+fn parse_quoted(text: &str) -> Option<String> {
+    let mut chars = text.split_once('"')?.1.chars();
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+fn parse_problem(message: &str) -> Problem {
+    Problem {
+        path: parse_quoted(&message),
+        message: message.to_string(),
+        unreadable_file: UNREADABLE_FILE_PREFIXES
+            .iter()
+            .any(|prefix| message.starts_with(prefix)),
+    }
+}
+
+pub fn parse_output(output: &str) -> Report {
+    let mut report = Report::default();
+    for line in output.lines().map(str::trim) {
+        if let Some(summary) = line.strip_prefix("fsck summary:") {
+            report.summary = summary.trim().to_string();
+        } else if let Some(message) = line.strip_prefix("fsck:") {
+            report.problems.push(parse_problem(message.trim()));
+        }
+    }
+
+    report
+}
+
+/// Runs an integrity check of a user's encrypted storage. Their home directory must not be in use.
+pub fn check(user: &str, password: &str) -> Result<Report> {
+    info!("Checking encrypted storage of user '{}'", &user);
+    activity::ensure_idle(&user)?;
+    rekey::verify_password(&user, &password)?;
+
+    let output = gocryptfs::fsck(&encrypted_storage_path(&user), &password)?;
+    let report = parse_output(&output);
+    info!(
+        "Found {} problem(s) in encrypted storage of user '{}'",
+        report.problems.len(),
+        &user
+    );
+
+    Ok(report)
+}
+
+// Reported paths come from the storage itself: never let them point outside of it
+fn is_safe_relative_path(path: &str) -> bool {
+    Path::new(&path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn unique_destination(dir: &Path, path: &str) -> std::path::PathBuf {
+    let name = path.replace('/', "_");
+    let mut destination = dir.join(&name);
+    let mut suffix = 1;
+    while destination.exists() {
+        destination = dir.join(format!("{}.{}", &name, &suffix));
+        suffix += 1;
+    }
+
+    destination
+}
+
+fn move_files(mountpoint: &str, files: &[String]) -> Result<Vec<String>> {
+    let damaged_files_dir = Path::new(&mountpoint).join(&DAMAGED_FILES_DIR);
+    if !damaged_files_dir.exists() {
+        fs::create_dir(&damaged_files_dir)
+            .with_context(|| "Failed to create damaged files directory")?;
+        let root_metadata = fs::metadata(&mountpoint)?;
+        chown(
+            &damaged_files_dir,
+            Some(root_metadata.uid()),
+            Some(root_metadata.gid()),
+        )?;
+    }
+
+    let mut moved = Vec::new();
+    for file in files {
+        if !is_safe_relative_path(&file) || file.starts_with(&DAMAGED_FILES_DIR) {
+            continue;
+        }
+        let destination = unique_destination(&damaged_files_dir, &file);
+        if let Err(e) = fs::rename(Path::new(&mountpoint).join(&file), &destination) {
+            error!("Failed to move damaged file '{}' aside: {}", &file, e);
+        } else {
+            moved.push(file.to_string());
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Checks a user's encrypted storage and moves the files it could not read into `DAMAGED_FILES_DIR`, so that
+/// the rest of the home directory stays usable. Returns the report and the files which were moved.
+pub fn move_damaged_files(user: &str, password: &str) -> Result<(Report, Vec<String>)> {
+    let report = check(&user, &password)?;
+    let files = report.damaged_files();
+    if files.is_empty() {
+        return Ok((report, Vec::new()));
+    }
+
+    info!(
+        "Moving {} damaged file(s) of user '{}' aside",
+        files.len(),
+        &user
+    );
+    let mountpoint = rekey::scratch_mountpoint(&user, "fsck");
+    fs::create_dir_all(&mountpoint)?;
+    let result = gocryptfs::mount(
+        &encrypted_storage_path(&user),
+        &mountpoint,
+        &password,
+        false,
+    )
+    .and_then(|_| move_files(&mountpoint, &files));
+    rekey::unmount(&mountpoint);
+
+    Ok((report, result?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `gocryptfs -fsck` output, as printed for a volume with a tampered name and two unreadable files
+    const DAMAGED_OUTPUT: &str = "Password: \nDecrypting master key\n\
+        fsck: corrupt entry in dir \"Pictures\": \"dQ2x6Y7wH9Aq\"\n\
+        fsck: error reading file \"Documents/report \\\"final\\\".odt\" (inum 1843): input/output error\n\
+        fsck: corrupt file \"Music/track.flac\" (inum 1907): decrypt failed\n\
+        fsck: error reading file \"Documents/report \\\"final\\\".odt\" (inum 1843): input/output error\n\
+        fsck summary: 3 corrupt files\n";
+    const CLEAN_OUTPUT: &str =
+        "Password: \nDecrypting master key\nfsck summary: no problems found\n";
+
+    #[test]
+    fn parses_clean_output() {
+        let report = parse_output(&CLEAN_OUTPUT);
+        assert!(report.is_clean());
+        assert_eq!(report.summary, "no problems found");
+        assert!(report.damaged_files().is_empty());
+    }
+
+    #[test]
+    fn parses_problems() {
+        let report = parse_output(&DAMAGED_OUTPUT);
+        assert_eq!(report.summary, "3 corrupt files");
+        assert_eq!(report.problems.len(), 4);
+
+        let entry = &report.problems[0];
+        assert_eq!(entry.path.as_deref(), Some("Pictures"));
+        assert!(!entry.unreadable_file);
+
+        let file = &report.problems[1];
+        assert_eq!(file.path.as_deref(), Some("Documents/report \"final\".odt"));
+        assert!(file.unreadable_file);
+        assert!(file.message.starts_with("error reading file"));
+    }
+
+    #[test]
+    fn lists_damaged_files_once() {
+        let report = parse_output(&DAMAGED_OUTPUT);
+        assert_eq!(
+            report.damaged_files(),
+            vec!["Documents/report \"final\".odt", "Music/track.flac"]
+        );
+    }
+
+    #[test]
+    fn refuses_paths_leaving_the_storage() {
+        assert!(is_safe_relative_path("Documents/report.odt"));
+        assert!(!is_safe_relative_path("../etc/shadow"));
+        assert!(!is_safe_relative_path("/etc/shadow"));
+        assert!(!is_safe_relative_path("Documents/../../etc"));
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::{Context, Result};
+use log::warn;
+use std::{
+    ffi::CString,
+    fs,
+    io::{Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt, lchown, symlink},
+    },
+    path::Path,
+};
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Total size in bytes of the regular files below a path, not following symbolic links
+pub fn dir_size(path: &str) -> Result<u64> {
+    let mut size = 0;
+    for entry in
+        fs::read_dir(&path).with_context(|| format!("Failed to read directory '{}'", &path))?
+    {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path().to_string_lossy())?;
+        } else if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+fn statvfs(path: &str) -> Result<libc::statvfs> {
+    let c_path = CString::new(path)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to get free space of '{}'", &path));
+    }
+
+    Ok(stat)
+}
+
+/// Space available to unprivileged users on the filesystem holding a path, in bytes
+pub fn free_space(path: &str) -> Result<u64> {
+    let stat = statvfs(&path)?;
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Size of the filesystem holding a path, in bytes
+pub fn total_space(path: &str) -> Result<u64> {
+    let stat = statvfs(&path)?;
+
+    Ok(stat.f_blocks as u64 * stat.f_frsize as u64)
+}
+
+/// Human-readable size in binary units, e.g. "1.5 GiB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Atomically swaps two paths on the same filesystem
+pub fn exchange(first: &str, second: &str) -> Result<()> {
+    let c_first = CString::new(first)?;
+    let c_second = CString::new(second)?;
+    if unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            c_first.as_ptr(),
+            libc::AT_FDCWD,
+            c_second.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to exchange '{}' and '{}'", &first, &second));
+    }
+
+    Ok(())
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+fn xattr_names(path: &CString) -> Result<Vec<CString>> {
+    let size = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() == Some(libc::ENOTSUP) {
+            return Ok(Vec::new());
+        }
+        return Err(error).with_context(|| "Failed to list extended attributes");
+    }
+
+    let mut buffer = vec![0u8; size as usize];
+    let size = unsafe {
+        libc::llistxattr(
+            path.as_ptr(),
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+        )
+    };
+    if size < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| "Failed to list extended attributes");
+    }
+    buffer.truncate(size as usize);
+
+    Ok(buffer
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| CString::new(name).ok())
+        .collect())
+}
+
+fn xattr_value(path: &CString, name: &CString) -> Result<Vec<u8>> {
+    let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| "Failed to read extended attribute");
+    }
+
+    let mut value = vec![0u8; size as usize];
+    let size = unsafe {
+        libc::lgetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_mut_ptr() as *mut libc::c_void,
+            value.len(),
+        )
+    };
+    if size < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| "Failed to read extended attribute");
+    }
+    value.truncate(size as usize);
+
+    Ok(value)
+}
+
+// Attributes the destination filesystem refuses (e.g. security labels on FUSE) are skipped with a warning
+fn copy_xattrs(source: &Path, destination: &Path) -> Result<()> {
+    let c_source = path_to_cstring(&source)?;
+    let c_destination = path_to_cstring(&destination)?;
+    for name in xattr_names(&c_source)? {
+        let value = xattr_value(&c_source, &name)?;
+        if unsafe {
+            libc::lsetxattr(
+                c_destination.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        } != 0
+        {
+            warn!(
+                "Failed to copy extended attribute '{}' of '{}': {}",
+                name.to_string_lossy(),
+                source.to_string_lossy(),
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_metadata(
+    source_path: &Path,
+    source: &fs::Metadata,
+    destination: &Path,
+    owner: Option<(u32, u32)>,
+) -> Result<()> {
+    let (uid, gid) = owner.unwrap_or((source.uid(), source.gid()));
+    lchown(&destination, Some(uid), Some(gid))?;
+    if !source.file_type().is_symlink() {
+        fs::set_permissions(&destination, fs::Permissions::from_mode(source.mode()))?;
+    }
+    copy_xattrs(&source_path, &destination)?;
+
+    Ok(())
+}
+
+fn copy_file(source: &Path, destination: &Path, progress: &mut dyn FnMut(u64)) -> Result<()> {
+    let mut reader = fs::File::open(&source)?;
+    let mut writer = fs::File::create(&destination)?;
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let length = reader.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..length])?;
+        progress(length as u64);
+    }
+    writer.sync_all()?;
+
+    Ok(())
+}
+
+/// Recursively copies the contents of a directory, preserving ownership, permissions, extended attributes and
+/// symbolic links. Calls `progress` with the number of bytes copied since its previous call.
+pub fn copy_tree(source: &str, destination: &str, progress: &mut dyn FnMut(u64)) -> Result<()> {
+    copy_tree_as(&source, &destination, None, progress)
+}
+
+/// Like `copy_tree`, but everything copied is owned by `owner` (user and group IDs) when given. Existing
+/// directories are merged into, and other existing entries replaced.
+pub fn copy_tree_as(
+    source: &str,
+    destination: &str,
+    owner: Option<(u32, u32)>,
+    progress: &mut dyn FnMut(u64),
+) -> Result<()> {
+    for entry in
+        fs::read_dir(&source).with_context(|| format!("Failed to read directory '{}'", &source))?
+    {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let source_path = entry.path();
+        let destination_path = Path::new(&destination).join(entry.file_name());
+        let file_type = metadata.file_type();
+        // Sockets, FIFOs and device nodes do not belong in home directories
+        if !file_type.is_dir() && !file_type.is_file() && !file_type.is_symlink() {
+            continue;
+        }
+
+        // Replaced entries are removed first, so that nothing is written through a symbolic link
+        let merge = match fs::symlink_metadata(&destination_path) {
+            Ok(existing) if existing.is_dir() && file_type.is_dir() => true,
+            Ok(existing) if existing.is_dir() => {
+                fs::remove_dir_all(&destination_path)?;
+                false
+            }
+            Ok(_) => {
+                fs::remove_file(&destination_path)?;
+                false
+            }
+            Err(_) => false,
+        };
+
+        if file_type.is_dir() {
+            if !merge {
+                fs::create_dir(&destination_path)?;
+            }
+            copy_tree_as(
+                &source_path.to_string_lossy(),
+                &destination_path.to_string_lossy(),
+                owner,
+                progress,
+            )?;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(&source_path)?, &destination_path)?;
+        } else {
+            copy_file(&source_path, &destination_path, progress)
+                .with_context(|| format!("Failed to copy '{}'", &source_path.to_string_lossy()))?;
+        }
+        copy_metadata(&source_path, &metadata, &destination_path, owner)?;
+    }
+
+    Ok(())
+}
+
+fn files_match(source: &Path, destination: &Path) -> Result<bool> {
+    let mut source_reader = fs::File::open(&source)?;
+    let mut destination_reader = fs::File::open(&destination)?;
+    let mut source_buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut destination_buffer = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let length = source_reader.read(&mut source_buffer)?;
+        if length == 0 {
+            return Ok(destination_reader.read(&mut destination_buffer[..1])? == 0);
+        }
+        destination_reader.read_exact(&mut destination_buffer[..length])?;
+        if source_buffer[..length] != destination_buffer[..length] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Checks that a copy made with `copy_tree` has the same structure, ownership, permissions and contents as its
+/// source
+pub fn verify_tree(source: &str, destination: &str) -> Result<()> {
+    for entry in
+        fs::read_dir(&source).with_context(|| format!("Failed to read directory '{}'", &source))?
+    {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let source_path = entry.path();
+        let destination_path = Path::new(&destination).join(entry.file_name());
+        if !metadata.is_dir() && !metadata.is_file() && !metadata.file_type().is_symlink() {
+            continue;
+        }
+
+        let mismatch = || {
+            anyhow::anyhow!(
+                "Copy of '{}' does not match the original",
+                source_path.to_string_lossy()
+            )
+        };
+        let destination_metadata =
+            fs::symlink_metadata(&destination_path).map_err(|_| mismatch())?;
+        if destination_metadata.file_type() != metadata.file_type()
+            || destination_metadata.uid() != metadata.uid()
+            || destination_metadata.gid() != metadata.gid()
+        {
+            return Err(mismatch());
+        }
+
+        if metadata.is_dir() {
+            verify_tree(
+                &source_path.to_string_lossy(),
+                &destination_path.to_string_lossy(),
+            )?;
+        } else if metadata.file_type().is_symlink() {
+            if fs::read_link(&source_path)? != fs::read_link(&destination_path)? {
+                return Err(mismatch());
+            }
+        } else if destination_metadata.mode() != metadata.mode()
+            || destination_metadata.len() != metadata.len()
+            || !files_match(&source_path, &destination_path)?
+        {
+            return Err(mismatch());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,525 @@
+use anyhow::{Context, Result};
+use libqinit::storage_encryption::GOCRYPTFS_BINARY;
+use serde::Deserialize;
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::MetadataExt,
+    process::{Command, Output, Stdio},
+};
+
+use crate::wipe;
+
+pub const GOCRYPTFS_XRAY_BINARY: &str = "/usr/bin/gocryptfs-xray";
+pub const MASTER_KEY_LENGTH: usize = 32;
+// Same grouping as gocryptfs uses when printing master keys
+const MASTER_KEY_GROUP_LENGTH: usize = 8;
+pub const CONFIG_FILE: &str = "gocryptfs.conf";
+// Copy of the previous configuration kept by `-passwd`
+pub const CONFIG_BACKUP_FILE: &str = "gocryptfs.conf.bak";
+// Current gocryptfs on-disk format
+const CURRENT_VERSION: u16 = 2;
+// Default scrypt cost used by gocryptfs (2^16)
+const RECOMMENDED_SCRYPT_N: u64 = 65536;
+// Exit code of gocryptfs -fsck when it found corrupted data
+const FSCK_ERRORS_EXIT_CODE: i32 = 26;
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(anyhow::anyhow!("Invalid hexadecimal string"));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16).with_context(|| "Invalid hexadecimal string")
+        })
+        .collect()
+}
+
+/// Formats a master key in dash-separated groups, as gocryptfs does
+pub fn format_master_key(master_key: &[u8]) -> String {
+    encode_hex(&master_key)
+        .as_bytes()
+        .chunks(MASTER_KEY_GROUP_LENGTH)
+        .map(|group| String::from_utf8_lossy(group).to_string())
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// Accepts master keys with or without dashes and whitespace, in any case
+pub fn parse_master_key(master_key: &str) -> Result<Vec<u8>> {
+    let hex: String = master_key
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect();
+    let master_key = decode_hex(&hex.to_lowercase())?;
+    if master_key.len() != MASTER_KEY_LENGTH {
+        return Err(anyhow::anyhow!(
+            "Unexpected master key length ({} bytes)",
+            master_key.len()
+        ));
+    }
+
+    Ok(master_key)
+}
+
+// Secrets are passed through standard input so that they do not show up in the process list
+fn output_with_stdin(binary: &str, args: &[&str], input: &str) -> Result<Output> {
+    let mut child = Command::new(&binary)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", &binary))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+
+    Ok(child.wait_with_output()?)
+}
+
+pub(crate) fn run_with_stdin(binary: &str, args: &[&str], input: &str) -> Result<String> {
+    let output = output_with_stdin(&binary, &args, &input)?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "'{}' failed: {}",
+            &binary,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn dump_master_key(encrypted_storage_path: &str, password: &str) -> Result<Vec<u8>> {
+    let output = run_with_stdin(
+        &GOCRYPTFS_XRAY_BINARY,
+        &[
+            "-dumpmasterkey",
+            &format!("{}/gocryptfs.conf", &encrypted_storage_path),
+        ],
+        &format!("{}\n", &password),
+    )
+    .with_context(|| "Failed to read encrypted storage's master key")?;
+
+    output
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("No master key found in output"))
+        .and_then(parse_master_key)
+}
+
+/// Creates a new encrypted storage, using gocryptfs' default scrypt cost unless one is given
+pub fn init(encrypted_storage_path: &str, password: &str, scrypt_log_n: Option<u8>) -> Result<()> {
+    let scrypt_argument = scrypt_log_n.map(|log_n| format!("-scryptn={}", log_n));
+    let mut args = vec!["-init"];
+    if let Some(scrypt_argument) = &scrypt_argument {
+        args.push(&scrypt_argument);
+    }
+    args.push(&encrypted_storage_path);
+    run_with_stdin(
+        &GOCRYPTFS_BINARY,
+        &args,
+        &format!("{}\n{}\n", &password, &password),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to initialize encrypted storage at path '{}'",
+            &encrypted_storage_path
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Re-wraps the master key with the same password, deriving it with a higher scrypt cost
+pub fn set_scrypt_cost(
+    encrypted_storage_path: &str,
+    password: &str,
+    scrypt_log_n: u8,
+) -> Result<()> {
+    run_with_stdin(
+        &GOCRYPTFS_BINARY,
+        &[
+            "-passwd",
+            &format!("-scryptn={}", scrypt_log_n),
+            &encrypted_storage_path,
+        ],
+        &format!("{}\n{}\n", &password, &password),
+    )
+    .with_context(|| "Failed to change encrypted storage's scrypt cost")?;
+
+    Ok(())
+}
+
+pub fn mount(
+    encrypted_storage_path: &str,
+    mountpoint: &str,
+    password: &str,
+    read_only: bool,
+) -> Result<()> {
+    let mut args = Vec::new();
+    if read_only {
+        args.push("-ro");
+    }
+    args.push(&encrypted_storage_path);
+    args.push(&mountpoint);
+    run_with_stdin(&GOCRYPTFS_BINARY, &args, &format!("{}\n", &password)).with_context(|| {
+        format!(
+            "Failed to mount encrypted storage '{}' at '{}'",
+            &encrypted_storage_path, &mountpoint
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Mounts an encrypted storage read-only using its master key. gocryptfs does not check the key: files
+/// simply fail to decrypt if it is wrong.
+pub fn mount_with_master_key(
+    encrypted_storage_path: &str,
+    mountpoint: &str,
+    master_key: &[u8],
+) -> Result<()> {
+    run_with_stdin(
+        &GOCRYPTFS_BINARY,
+        &[
+            "-ro",
+            "-masterkey=stdin",
+            &encrypted_storage_path,
+            &mountpoint,
+        ],
+        &format!("{}\n", &encode_hex(&master_key)),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to mount encrypted storage '{}' at '{}' using master key",
+            &encrypted_storage_path, &mountpoint
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Checks the integrity of an encrypted storage, returning gocryptfs' report. Finding corrupted data is not an
+/// error.
+pub fn fsck(encrypted_storage_path: &str, password: &str) -> Result<String> {
+    let output = output_with_stdin(
+        &GOCRYPTFS_BINARY,
+        &["-fsck", &encrypted_storage_path],
+        &format!("{}\n", &password),
+    )?;
+    if !output.status.success() && output.status.code() != Some(FSCK_ERRORS_EXIT_CODE) {
+        return Err(anyhow::anyhow!(
+            "Failed to check encrypted storage: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    ))
+}
+
+/// Re-wraps the master key of an encrypted storage with a new password, without needing the old one
+pub fn set_password_with_master_key(
+    encrypted_storage_path: &str,
+    master_key: &[u8],
+    new_password: &str,
+) -> Result<()> {
+    run_with_stdin(
+        &GOCRYPTFS_BINARY,
+        &["-passwd", "-masterkey=stdin", &encrypted_storage_path],
+        &format!("{}\n{}\n", &encode_hex(&master_key), &new_password),
+    )
+    .with_context(|| "Failed to set encrypted storage's password from master key")?;
+
+    Ok(())
+}
+
+/// The backup `-passwd` leaves behind still unwraps the master key with the previous password, and makes the
+/// next `-passwd` fail. It is overwritten, unless it is still a link to the current configuration.
+pub fn remove_config_backup(encrypted_storage_path: &str) -> Result<()> {
+    let backup_path = format!("{}/{}", &encrypted_storage_path, &CONFIG_BACKUP_FILE);
+    if !fs::exists(&backup_path)? {
+        return Ok(());
+    }
+
+    let backup_metadata = fs::metadata(&backup_path)?;
+    let config_metadata = fs::metadata(&format!("{}/{}", &encrypted_storage_path, &CONFIG_FILE))?;
+    if backup_metadata.dev() == config_metadata.dev()
+        && backup_metadata.ino() == config_metadata.ino()
+    {
+        fs::remove_file(&backup_path)
+            .with_context(|| "Failed to remove encrypted storage's configuration backup")?;
+    } else {
+        wipe::overwrite_and_remove(&backup_path)
+            .with_context(|| "Failed to wipe encrypted storage's configuration backup")?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScryptParameters {
+    #[serde(rename = "Salt")]
+    pub salt: String,
+    #[serde(rename = "N")]
+    pub n: u64,
+    #[serde(rename = "R")]
+    pub r: u64,
+    #[serde(rename = "P")]
+    pub p: u64,
+    #[serde(rename = "KeyLen")]
+    pub key_len: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    #[serde(rename = "Creator", default)]
+    pub creator: String,
+    #[serde(rename = "EncryptedKey", default)]
+    pub encrypted_key: String,
+    #[serde(rename = "ScryptObject")]
+    pub scrypt: ScryptParameters,
+    #[serde(rename = "Version")]
+    pub version: u16,
+    #[serde(rename = "FeatureFlags", default)]
+    pub feature_flags: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentCipher {
+    AesGcm,
+    AesSiv,
+    XChaCha20Poly1305,
+}
+
+impl ContentCipher {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCipher::AesGcm => "AES-256-GCM",
+            ContentCipher::AesSiv => "AES-SIV-512",
+            ContentCipher::XChaCha20Poly1305 => "XChaCha20-Poly1305",
+        }
+    }
+}
+
+impl Config {
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.feature_flags
+            .iter()
+            .any(|feature_flag| feature_flag == flag)
+    }
+
+    pub fn content_cipher(&self) -> ContentCipher {
+        if self.has_flag("XChaCha20Poly1305") {
+            ContentCipher::XChaCha20Poly1305
+        } else if self.has_flag("AESSIV") {
+            ContentCipher::AesSiv
+        } else {
+            ContentCipher::AesGcm
+        }
+    }
+
+    pub fn plaintext_names(&self) -> bool {
+        self.has_flag("PlaintextNames")
+    }
+
+    pub fn long_names(&self) -> bool {
+        self.has_flag("LongNames")
+    }
+
+    /// Human-readable descriptions of weak parameters and legacy format features
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.version < CURRENT_VERSION {
+            warnings.push(format!(
+                "Legacy on-disk format version {} (current is {})",
+                self.version, CURRENT_VERSION
+            ));
+        }
+        if !self.has_flag("HKDF") {
+            warnings.push("Legacy key derivation without HKDF".to_string());
+        }
+        if self.content_cipher() == ContentCipher::AesGcm && !self.has_flag("GCMIV128") {
+            warnings.push("Legacy 96-bit GCM nonces".to_string());
+        }
+        if self.plaintext_names() {
+            warnings.push("File names are not encrypted".to_string());
+        } else {
+            if !self.has_flag("EMENames") {
+                warnings.push("Legacy file name encryption without EME".to_string());
+            }
+            if !self.has_flag("DirIV") {
+                warnings.push("File names are encrypted without per-directory IVs".to_string());
+            }
+        }
+        if self.scrypt.n < RECOMMENDED_SCRYPT_N {
+            warnings.push(format!(
+                "Weak password hashing: scrypt N={} (recommended: at least {})",
+                self.scrypt.n, RECOMMENDED_SCRYPT_N
+            ));
+        }
+        if self.scrypt.key_len != MASTER_KEY_LENGTH as u64 {
+            warnings.push(format!(
+                "Unexpected scrypt key length of {} bytes",
+                self.scrypt.key_len
+            ));
+        }
+
+        warnings
+    }
+}
+
+pub fn read_config(encrypted_storage_path: &str) -> Result<Config> {
+    let path = format!("{}/{}", &encrypted_storage_path, &CONFIG_FILE);
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read '{}'", &path))?;
+
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse '{}'", &path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As written by `gocryptfs -init` 2.4 with default options
+    const CURRENT_CONFIG: &str = r#"{
+	"Creator": "gocryptfs v2.4.0",
+	"EncryptedKey": "ZrL3kXHPw5Z0Iuh2sLq2hJ3Xa4mRkqYxlv9cJ1v0Hf3pUwGmT6cN8o7yQe4dBaVz5gKjS2rT1uE0iWcA",
+	"ScryptObject": {
+		"Salt": "Vb2x1t9Yd3Qm8n0Kp5wHc7rLz4sJf6gTa1eUo2iR3yM=",
+		"N": 65536,
+		"R": 8,
+		"P": 1,
+		"KeyLen": 32
+	},
+	"Version": 2,
+	"FeatureFlags": [
+		"HKDF",
+		"GCMIV128",
+		"DirIV",
+		"EMENames",
+		"LongNames",
+		"Raw64"
+	]
+}"#;
+    // As written by gocryptfs 0.x with `-plaintextnames` and a low scrypt cost
+    const LEGACY_CONFIG: &str = r#"{
+	"Creator": "gocryptfs v0.7",
+	"EncryptedKey": "kQ1c8vWz0Hn5Jr2Lp7Xs3Ty6Ub9Ad4Fe1Gh",
+	"ScryptObject": {
+		"Salt": "Qm8n0Kp5wHc7rLz4sJf6gTa1eUo2iR3yVb2x1t9Yd3Q=",
+		"N": 1024,
+		"R": 8,
+		"P": 1,
+		"KeyLen": 32
+	},
+	"Version": 1,
+	"FeatureFlags": [
+		"PlaintextNames"
+	]
+}"#;
+
+    #[test]
+    fn parses_config() {
+        let config: Config = serde_json::from_str(&CURRENT_CONFIG).unwrap();
+        assert_eq!(config.creator, "gocryptfs v2.4.0");
+        assert_eq!(config.version, 2);
+        assert_eq!(config.scrypt.n, 65536);
+        assert_eq!(config.scrypt.key_len, 32);
+        assert_eq!(config.content_cipher(), ContentCipher::AesGcm);
+        assert!(config.long_names());
+        assert!(!config.plaintext_names());
+        assert!(config.warnings().is_empty());
+    }
+
+    #[test]
+    fn reads_cipher_from_feature_flags() {
+        let mut config: Config = serde_json::from_str(&CURRENT_CONFIG).unwrap();
+        config.feature_flags.push("AESSIV".to_string());
+        assert_eq!(config.content_cipher(), ContentCipher::AesSiv);
+        config.feature_flags.push("XChaCha20Poly1305".to_string());
+        assert_eq!(config.content_cipher(), ContentCipher::XChaCha20Poly1305);
+        // Other ciphers carry their own nonces
+        config.feature_flags.retain(|flag| flag != "GCMIV128");
+        assert!(config.warnings().is_empty());
+    }
+
+    #[test]
+    fn warns_about_legacy_config() {
+        let config: Config = serde_json::from_str(&LEGACY_CONFIG).unwrap();
+        assert!(config.plaintext_names());
+        assert_eq!(
+            config.warnings(),
+            vec![
+                "Legacy on-disk format version 1 (current is 2)",
+                "Legacy key derivation without HKDF",
+                "Legacy 96-bit GCM nonces",
+                "File names are not encrypted",
+                "Weak password hashing: scrypt N=1024 (recommended: at least 65536)",
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_legacy_name_encryption() {
+        let mut config: Config = serde_json::from_str(&CURRENT_CONFIG).unwrap();
+        config
+            .feature_flags
+            .retain(|flag| flag != "EMENames" && flag != "DirIV");
+        config.scrypt.key_len = 16;
+        assert_eq!(
+            config.warnings(),
+            vec![
+                "Legacy file name encryption without EME",
+                "File names are encrypted without per-directory IVs",
+                "Unexpected scrypt key length of 16 bytes",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_config_without_scrypt_parameters() {
+        assert!(serde_json::from_str::<Config>(r#"{"Version": 2}"#).is_err());
+    }
+
+    #[test]
+    fn formats_and_parses_master_key() {
+        let master_key: Vec<u8> = (0..MASTER_KEY_LENGTH as u8).map(|i| i * 7).collect();
+        let formatted = format_master_key(&master_key);
+        assert_eq!(
+            formatted,
+            "00070e15-1c232a31-383f464d-545b6269-70777e85-8c939aa1-a8afb6bd-c4cbd2d9"
+        );
+        assert_eq!(parse_master_key(&formatted).unwrap(), master_key);
+        assert_eq!(
+            parse_master_key(&format!(
+                " {}\n",
+                formatted.to_uppercase().replace('-', " ")
+            ))
+            .unwrap(),
+            master_key
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_master_keys() {
+        // Too short, odd length, not hexadecimal and multi-byte characters
+        assert!(parse_master_key("00070e15-1c232a31").is_err());
+        assert!(parse_master_key(&"0".repeat(MASTER_KEY_LENGTH * 2 - 1)).is_err());
+        assert!(parse_master_key(&"g".repeat(MASTER_KEY_LENGTH * 2)).is_err());
+        assert!(parse_master_key(&"é".repeat(MASTER_KEY_LENGTH)).is_err());
+    }
+}
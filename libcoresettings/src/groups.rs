@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use libqinit::{OVERLAY_MOUNTPOINT, rootfs::run_chroot_command};
+use log::info;
+use std::fs;
+
+use crate::{config::Config, restrictions, users};
+
+// Same as SYS_GID_MAX in most login.defs files
+const SYSTEM_GID_MAX: u32 = 999;
+const GROUP_NAME_MAX_LENGTH: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct Group {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+fn parse_group_line(line: &str) -> Option<Group> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 4 {
+        return None;
+    }
+
+    let gid = fields[2].parse::<u32>().ok()?;
+    let members = if fields[3].is_empty() {
+        Vec::new()
+    } else {
+        fields[3]
+            .split(',')
+            .map(|member| member.to_string())
+            .collect()
+    };
+
+    Some(Group {
+        name: fields[0].to_string(),
+        gid,
+        members,
+    })
+}
+
+pub fn list() -> Result<Vec<Group>> {
+    let contents = fs::read_to_string(&format!("{}/etc/group", &OVERLAY_MOUNTPOINT))
+        .with_context(|| "Failed to read groups file")?;
+
+    Ok(contents.lines().filter_map(parse_group_line).collect())
+}
+
+pub fn get(group: &str) -> Result<Group> {
+    list()?
+        .into_iter()
+        .find(|entry| entry.name == group)
+        .ok_or_else(|| anyhow::anyhow!("Group '{}' does not exist", &group))
+}
+
+fn read_passwd() -> Result<String> {
+    fs::read_to_string(&format!("{}/etc/passwd", &OVERLAY_MOUNTPOINT))
+        .with_context(|| "Failed to read passwd file")
+}
+
+fn primary_group_ids() -> Result<Vec<u32>> {
+    Ok(read_passwd()?
+        .lines()
+        .filter_map(|line| line.split(':').nth(3)?.parse::<u32>().ok())
+        .collect())
+}
+
+pub fn primary_group_id(user: &str) -> Result<Option<u32>> {
+    Ok(read_passwd()?.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.first() == Some(&user) {
+            fields.get(3)?.parse::<u32>().ok()
+        } else {
+            None
+        }
+    }))
+}
+
+pub fn user_id(user: &str) -> Result<Option<u32>> {
+    Ok(read_passwd()?.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.first() == Some(&user) {
+            fields.get(2)?.parse::<u32>().ok()
+        } else {
+            None
+        }
+    }))
+}
+
+/// System groups, administrator groups and users' primary groups cannot be deleted
+pub fn is_protected(group: &Group) -> Result<bool> {
+    if group.gid <= SYSTEM_GID_MAX || Config::read()?.admin_groups.contains(&group.name) {
+        return Ok(true);
+    }
+
+    Ok(primary_group_ids()?.contains(&group.gid))
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid_first_char = chars
+        .next()
+        .map(|c| c.is_ascii_lowercase() || c == '_')
+        .unwrap_or(false);
+
+    if !valid_first_char
+        || name.len() > GROUP_NAME_MAX_LENGTH
+        || !chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        return Err(anyhow::anyhow!("Invalid group name '{}'", &name));
+    }
+
+    Ok(())
+}
+
+pub fn create(name: &str) -> Result<()> {
+    validate_name(&name)?;
+    if list()?.iter().any(|group| group.name == name) {
+        return Err(anyhow::anyhow!("Group '{}' already exists", &name));
+    }
+
+    info!("Creating group '{}'", &name);
+    run_chroot_command(&["/usr/sbin/groupadd", &name])
+        .with_context(|| format!("Failed to create group '{}'", &name))?;
+
+    Ok(())
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    let group = get(&name)?;
+    if is_protected(&group)? {
+        return Err(anyhow::anyhow!("Group '{}' is protected", &name));
+    }
+
+    info!("Deleting group '{}'", &name);
+    run_chroot_command(&["/usr/sbin/groupdel", &name])
+        .with_context(|| format!("Failed to delete group '{}'", &name))?;
+
+    Ok(())
+}
+
+/// Users listed in the group, followed by users who have it as their primary group
+pub fn members(group: &Group) -> Result<Vec<String>> {
+    let mut members = group.members.clone();
+    for line in read_passwd()?.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.get(3).and_then(|gid| gid.parse::<u32>().ok()) == Some(group.gid)
+            && !members.iter().any(|member| member == fields[0])
+        {
+            members.push(fields[0].to_string());
+        }
+    }
+
+    Ok(members)
+}
+
+/// Exact lookup in the group database, including the user's primary group
+pub fn is_member(user: &str, group: &str) -> Result<bool> {
+    Ok(members(&get(&group)?)?.iter().any(|member| member == user))
+}
+
+/// gpasswd only manages listed members, so users cannot be removed from their primary group with it
+pub fn is_primary_group(user: &str, group: &str) -> Result<bool> {
+    Ok(primary_group_id(&user)? == Some(get(&group)?.gid))
+}
+
+pub fn set_membership(user: &str, group: &str, member: bool) -> Result<()> {
+    let is_member = is_member(&user, &group)?;
+    if member == is_member {
+        info!(
+            "User '{}' membership of group '{}' is already {}",
+            &user, &group, &member
+        );
+        return Ok(());
+    }
+
+    if member {
+        // Membership of an administrator group is administrator rights, which restricted users cannot have
+        if restrictions::is_restricted(&user)
+            && Config::read()?
+                .admin_groups
+                .iter()
+                .any(|admin_group| admin_group == group)
+        {
+            return Err(anyhow::anyhow!(
+                "User '{}' is restricted: lift their restrictions first",
+                &user
+            ));
+        }
+        info!("Adding user '{}' to group '{}'", &user, &group);
+        users::add_to_group(&user, &group)
+    } else {
+        if is_primary_group(&user, &group)? {
+            return Err(anyhow::anyhow!(
+                "Group '{}' is the primary group of user '{}'",
+                &group,
+                &user
+            ));
+        }
+        info!("Removing user '{}' from group '{}'", &user, &group);
+        users::remove_from_group(&user, &group)
+    }
+}
@@ -1 +1,27 @@
+pub mod activity;
+pub mod audit;
+pub mod autologin;
+pub mod backup;
+pub mod config;
+pub mod encryption;
+pub mod escrow;
+pub mod fsck;
+pub mod fsutil;
+pub mod gocryptfs;
+pub mod groups;
+pub mod lockout;
+pub mod logins;
+pub mod loginscreen;
+pub mod password_history;
+pub mod pin;
+pub mod privilege;
+pub mod profiles;
+pub mod rekey;
+pub mod removable;
+pub mod restrictions;
+pub mod session;
+pub mod skeleton;
+pub mod time;
+pub mod usage;
 pub mod users;
+pub mod wipe;
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::{fmt, fs, os::unix::fs::PermissionsExt, time::Duration};
+
+use crate::{config, time::now};
+
+const FAILURES_FILE: &str = "login-failures";
+// Failed attempts allowed before any delay is enforced
+const FREE_ATTEMPTS: u32 = 3;
+const BASE_DELAY_SECS: u64 = 30;
+const MAX_DELAY_SECS: u64 = 3600;
+
+#[derive(Debug)]
+pub struct LockedOut {
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for LockedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many failed attempts: try again in {} second(s)",
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for LockedOut {}
+
+struct FailureRecord {
+    user: String,
+    failures: u32,
+    last_failure: u64,
+}
+
+fn read_records() -> Result<Vec<FailureRecord>> {
+    let path = config::state_path(&FAILURES_FILE);
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+
+    let contents =
+        fs::read_to_string(&path).with_context(|| "Failed to read login failures file")?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() != 3 {
+                warn!("Ignoring malformed login failure record '{}'", &line);
+                return None;
+            }
+
+            Some(FailureRecord {
+                user: fields[0].to_string(),
+                failures: fields[1].parse().ok()?,
+                last_failure: fields[2].parse().ok()?,
+            })
+        })
+        .collect())
+}
+
+fn write_records(records: &[FailureRecord]) -> Result<()> {
+    config::create_state_dir()?;
+    let contents: String = records
+        .iter()
+        .map(|record| {
+            format!(
+                "{}:{}:{}\n",
+                &record.user, &record.failures, &record.last_failure
+            )
+        })
+        .collect();
+
+    let path = config::state_path(&FAILURES_FILE);
+    let temporary_path = format!("{}.new", &path);
+    fs::write(&temporary_path, &contents).with_context(|| "Failed to write login failures file")?;
+    fs::set_permissions(&temporary_path, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&temporary_path, &path).with_context(|| "Failed to write login failures file")?;
+
+    Ok(())
+}
+
+/// Delay doubles with every failure past the free attempts, up to a one hour lockout
+fn delay_for(failures: u32) -> Duration {
+    if failures < FREE_ATTEMPTS {
+        return Duration::ZERO;
+    }
+
+    let exponent = (failures - FREE_ATTEMPTS).min(16);
+    Duration::from_secs((BASE_DELAY_SECS << exponent).min(MAX_DELAY_SECS))
+}
+
+/// Returns how long the user has to wait before trying again, if at all
+pub fn retry_after(user: &str) -> Result<Option<Duration>> {
+    let Some(record) = read_records()?
+        .into_iter()
+        .find(|record| record.user == user)
+    else {
+        return Ok(None);
+    };
+
+    // Do not let a clock going backwards extend the delay
+    let elapsed = Duration::from_secs(now().saturating_sub(record.last_failure));
+    let delay = delay_for(record.failures);
+    if elapsed >= delay {
+        Ok(None)
+    } else {
+        Ok(Some(delay - elapsed))
+    }
+}
+
+pub fn check(user: &str) -> Result<()> {
+    if let Some(retry_after) = retry_after(&user)? {
+        return Err(LockedOut { retry_after }.into());
+    }
+
+    Ok(())
+}
+
+pub fn record_failure(user: &str) -> Result<()> {
+    let mut records = read_records()?;
+    let timestamp = now();
+    let failures = if let Some(record) = records.iter_mut().find(|record| record.user == user) {
+        record.failures = record.failures.saturating_add(1);
+        record.last_failure = timestamp;
+        record.failures
+    } else {
+        records.push(FailureRecord {
+            user: user.to_string(),
+            failures: 1,
+            last_failure: timestamp,
+        });
+        1
+    };
+
+    warn!(
+        "Recorded failed authentication attempt {} for user '{}'",
+        &failures, &user
+    );
+    write_records(&records)
+}
+
+pub fn reset(user: &str) -> Result<()> {
+    let mut records = read_records()?;
+    let count = records.len();
+    records.retain(|record| record.user != user);
+    if records.len() != count {
+        info!(
+            "Resetting failed authentication attempts for user '{}'",
+            &user
+        );
+        write_records(&records)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let delays: Vec<(u32, u64)> = vec![
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 30),
+            (4, 60),
+            (5, 120),
+            (8, 960),
+            (9, 1920),
+            (10, 3600),
+            (11, 3600),
+        ];
+        for (failures, delay) in delays {
+            assert_eq!(
+                delay_for(failures),
+                Duration::from_secs(delay),
+                "{} failures",
+                failures
+            );
+        }
+    }
+
+    #[test]
+    fn delay_does_not_overflow() {
+        for failures in [FREE_ATTEMPTS + 16, FREE_ATTEMPTS + 64, u32::MAX] {
+            assert_eq!(delay_for(failures), Duration::from_secs(MAX_DELAY_SECS));
+        }
+    }
+}
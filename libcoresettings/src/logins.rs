@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use libqinit::OVERLAY_MOUNTPOINT;
+use log::info;
+use std::{collections::HashMap, fs, time::UNIX_EPOCH};
+
+use crate::{
+    activity::{
+        USER_PROCESS, UTMP_LINE_OFFSET, UTMP_LINE_SIZE, UTMP_RECORD_SIZE, UTMP_TYPE_OFFSET,
+        UTMP_USER_OFFSET, UTMP_USER_SIZE, utmp_string,
+    },
+    config::Config,
+    time,
+    users::encrypted_storage_path,
+};
+
+/// Login history of the system the user boots into. The rotated file is read first, if any.
+pub const WTMP_FILES: [&str; 2] = ["/var/log/wtmp.1", "/var/log/wtmp"];
+// Seconds part of ut_tv, which glibc and musl keep 32 bits wide
+const UTMP_TIME_OFFSET: usize = 340;
+const BOOT_TIME: i16 = 2;
+const DEAD_PROCESS: i16 = 8;
+
+#[derive(Clone, Debug)]
+pub struct LoginSession {
+    pub user: String,
+    /// Terminal the user logged in on
+    pub line: String,
+    pub start: u64,
+    /// None if the session is still going, or if the system stopped without recording its end
+    pub end: Option<u64>,
+}
+
+/// Pairs logins with the logouts on the same terminal. A reboot ends every session.
+pub fn parse_wtmp(contents: &[u8]) -> Vec<LoginSession> {
+    let mut sessions: Vec<LoginSession> = Vec::new();
+    // Index in `sessions` of the session going on on each terminal
+    let mut open_sessions: HashMap<String, usize> = HashMap::new();
+    for record in contents.chunks_exact(UTMP_RECORD_SIZE) {
+        let Ok(record_type) = record[UTMP_TYPE_OFFSET..UTMP_TYPE_OFFSET + 2].try_into() else {
+            continue;
+        };
+        let Ok(seconds) = record[UTMP_TIME_OFFSET..UTMP_TIME_OFFSET + 4].try_into() else {
+            continue;
+        };
+        let timestamp = i32::from_ne_bytes(seconds).max(0) as u64;
+        let line = utmp_string(&record, UTMP_LINE_OFFSET, UTMP_LINE_SIZE);
+
+        match i16::from_ne_bytes(record_type) {
+            USER_PROCESS => {
+                if let Some(index) = open_sessions.remove(&line) {
+                    sessions[index].end = Some(timestamp);
+                }
+                open_sessions.insert(line.clone(), sessions.len());
+                sessions.push(LoginSession {
+                    user: utmp_string(&record, UTMP_USER_OFFSET, UTMP_USER_SIZE),
+                    line,
+                    start: timestamp,
+                    end: None,
+                });
+            }
+            DEAD_PROCESS => {
+                if let Some(index) = open_sessions.remove(&line) {
+                    sessions[index].end = Some(timestamp);
+                }
+            }
+            BOOT_TIME => {
+                // The end of sessions cut short by a crash is unknown
+                open_sessions.clear();
+            }
+            _ => (),
+        }
+    }
+
+    sessions
+}
+
+fn read_sessions() -> Result<Vec<LoginSession>> {
+    let mut contents = Vec::new();
+    for file in &WTMP_FILES {
+        let path = format!("{}/{}", &OVERLAY_MOUNTPOINT, &file);
+        if fs::exists(&path)? {
+            contents.extend(fs::read(&path).with_context(|| "Failed to read login history")?);
+        }
+    }
+
+    Ok(parse_wtmp(&contents))
+}
+
+/// Most recent sessions first
+pub fn history(user: &str) -> Result<Vec<LoginSession>> {
+    let mut sessions: Vec<LoginSession> = read_sessions()?
+        .into_iter()
+        .filter(|session| session.user == user)
+        .collect();
+    sessions.reverse();
+
+    Ok(sessions)
+}
+
+/// Users who have not logged in for longer than the configured stale account age. Users who never logged
+/// in are stale once their storage is that old, so that new users are not reported right away.
+pub fn stale_users(users: &[String]) -> Result<Vec<String>> {
+    let max_age = Config::read()?.stale_account_age.as_secs();
+    let sessions = read_sessions()?;
+    let now = time::now();
+
+    let stale_users: Vec<String> = users
+        .iter()
+        .filter(|user| {
+            let last_activity = sessions
+                .iter()
+                .filter(|session| &session.user == *user)
+                .map(|session| session.start)
+                .max()
+                .unwrap_or_else(|| {
+                    fs::metadata(&encrypted_storage_path(&user))
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                        .map(|age| age.as_secs())
+                        .unwrap_or(0)
+                });
+            now.saturating_sub(last_activity) > max_age
+        })
+        .cloned()
+        .collect();
+    info!("Found {} stale account(s)", stale_users.len());
+
+    Ok(stale_users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Written by glibc's updwtmpx() on x86_64: two boots, with a logout on tty1 in between
+    const WTMP: &[u8] = include_bytes!("../testdata/wtmp");
+
+    #[test]
+    fn pairs_logins_with_logouts() {
+        let sessions = parse_wtmp(&WTMP);
+        let sessions: Vec<(&str, &str, u64, Option<u64>)> = sessions
+            .iter()
+            .map(|session| {
+                (
+                    session.user.as_str(),
+                    session.line.as_str(),
+                    session.start,
+                    session.end,
+                )
+            })
+            .collect();
+        assert_eq!(
+            sessions,
+            vec![
+                ("alice", "tty1", 1760000100, Some(1760003700)),
+                // Cut short by the reboot
+                ("bob", "tty2", 1760000200, None),
+                ("alice", "tty1", 1760004000, None),
+                // Still going
+                ("bob", "tty1", 1760090100, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn ends_sessions_when_the_terminal_is_reused() {
+        // The logout of the first session on tty1 went missing
+        let mut contents = WTMP[..UTMP_RECORD_SIZE * 3].to_vec();
+        contents.extend_from_slice(&WTMP[UTMP_RECORD_SIZE * 4..UTMP_RECORD_SIZE * 5]);
+        let sessions = parse_wtmp(&contents);
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].end, Some(1760004000));
+        assert_eq!(sessions[2].end, None);
+    }
+}
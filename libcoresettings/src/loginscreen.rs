@@ -0,0 +1,150 @@
+use anyhow::Result;
+use libqinit::boot_config::BootConfig;
+use log::info;
+use std::sync::{Arc, Mutex};
+
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+
+/// Sorts users in login screen order. Users without a position come last, in the order they were given.
+pub fn sort(users: &mut [String], boot_config: Arc<Mutex<BootConfig>>) {
+    let boot_config = boot_config.lock().unwrap();
+    let order = &boot_config.system.user_order;
+    users.sort_by_key(|user| {
+        order
+            .iter()
+            .position(|ordered_user| ordered_user == user)
+            .unwrap_or(order.len())
+    });
+}
+
+/// Forgets the login screen settings of users who no longer exist
+pub fn prune(users: &[String], boot_config: Arc<Mutex<BootConfig>>) {
+    let mut boot_config = boot_config.lock().unwrap();
+    let system = &mut boot_config.system;
+    system.user_order.retain(|user| users.contains(&user));
+    system.hidden_users.retain(|user| users.contains(&user));
+    system.display_names.retain(|user, _| users.contains(&user));
+}
+
+/// Forgets the login screen settings of a deleted user
+pub fn remove(user: &str, boot_config: Arc<Mutex<BootConfig>>) {
+    let mut boot_config = boot_config.lock().unwrap();
+    let system = &mut boot_config.system;
+    system
+        .user_order
+        .retain(|ordered_user| ordered_user != user);
+    system
+        .hidden_users
+        .retain(|hidden_user| hidden_user != user);
+    system.display_names.remove(user);
+}
+
+/// Moves the user up (negative `offset`) or down the login screen. `users` is the full list of users, which
+/// all get a position the first time one is moved.
+pub fn move_user(
+    user: &str,
+    offset: i32,
+    users: &[String],
+    boot_config: Arc<Mutex<BootConfig>>,
+) -> Result<()> {
+    let mut users = users.to_vec();
+    sort(&mut users, boot_config.clone());
+    let Some(index) = users.iter().position(|ordered_user| ordered_user == user) else {
+        return Err(anyhow::anyhow!("User '{}' does not exist", &user));
+    };
+    let new_index = (index as i32 + offset).clamp(0, users.len() as i32 - 1) as usize;
+    if new_index != index {
+        info!(
+            "Moving user '{}' to position {} of the login screen",
+            &user,
+            new_index + 1
+        );
+        let user = users.remove(index);
+        users.insert(new_index, user);
+    }
+    boot_config.lock().unwrap().system.user_order = users;
+
+    Ok(())
+}
+
+pub fn is_hidden(user: &str, boot_config: Arc<Mutex<BootConfig>>) -> bool {
+    boot_config
+        .lock()
+        .unwrap()
+        .system
+        .hidden_users
+        .iter()
+        .any(|hidden_user| hidden_user == user)
+}
+
+/// Hidden users can still log in by typing their username, but at least one user has to remain listed
+pub fn set_hidden(
+    user: &str,
+    hidden: bool,
+    users: &[String],
+    boot_config: Arc<Mutex<BootConfig>>,
+) -> Result<()> {
+    let mut boot_config = boot_config.lock().unwrap();
+    let hidden_users = &mut boot_config.system.hidden_users;
+    hidden_users.retain(|hidden_user| hidden_user != user);
+    if hidden {
+        if users
+            .iter()
+            .all(|other_user| other_user == user || hidden_users.contains(&other_user))
+        {
+            return Err(anyhow::anyhow!(
+                "At least one user has to be shown on the login screen"
+            ));
+        }
+        info!("Hiding user '{}' from the login screen", &user);
+        hidden_users.push(user.to_string());
+    } else {
+        info!("Showing user '{}' on the login screen", &user);
+    }
+
+    Ok(())
+}
+
+pub fn display_name(user: &str, boot_config: Arc<Mutex<BootConfig>>) -> Option<String> {
+    boot_config
+        .lock()
+        .unwrap()
+        .system
+        .display_names
+        .get(user)
+        .cloned()
+}
+
+/// An empty name removes the display name, and the username is shown instead
+pub fn set_display_name(
+    user: &str,
+    display_name: &str,
+    boot_config: Arc<Mutex<BootConfig>>,
+) -> Result<()> {
+    let display_name = display_name.trim();
+    if display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        return Err(anyhow::anyhow!(
+            "Display name cannot be longer than {} characters",
+            MAX_DISPLAY_NAME_LENGTH
+        ));
+    }
+    if display_name.chars().any(|character| character.is_control()) {
+        return Err(anyhow::anyhow!(
+            "Display name cannot contain control characters"
+        ));
+    }
+
+    let display_names = &mut boot_config.lock().unwrap().system.display_names;
+    if display_name.is_empty() {
+        info!("Removing display name of user '{}'", &user);
+        display_names.remove(user);
+    } else {
+        info!(
+            "Setting display name of user '{}' to '{}'",
+            &user, &display_name
+        );
+        display_names.insert(user.to_string(), display_name.to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use libqinit::{OVERLAY_MOUNTPOINT, storage_encryption::DISABLED_MODE_PASSWORD};
+use log::{info, warn};
+use openssl::{base64, hash::MessageDigest, memcmp, pkcs5::pbkdf2_hmac, rand::rand_bytes};
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use crate::config::{self, Config};
+
+const PASSWORD_HISTORY_FILE: &str = "password_history";
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+// Every recent password is hashed again on each change: fewer iterations than for PINs keep that quick
+const PBKDF2_ITERATIONS: usize = 100_000;
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// A previous password, like in shadow's opasswd
+struct HistoryRecord {
+    user: String,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+fn read_records() -> Result<Vec<HistoryRecord>> {
+    let path = config::state_path(&PASSWORD_HISTORY_FILE);
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+
+    let contents =
+        fs::read_to_string(&path).with_context(|| "Failed to read password history file")?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            // Records hold hashes: do not log them
+            if fields.len() != 3 {
+                warn!("Ignoring malformed password history record");
+                return None;
+            }
+
+            Some(HistoryRecord {
+                user: fields[0].to_string(),
+                salt: base64::decode_block(&fields[1]).ok()?,
+                hash: base64::decode_block(&fields[2]).ok()?,
+            })
+        })
+        .collect())
+}
+
+fn write_records(records: &[HistoryRecord]) -> Result<()> {
+    config::create_state_dir()?;
+    let contents: String = records
+        .iter()
+        .map(|record| {
+            format!(
+                "{}:{}:{}\n",
+                &record.user,
+                &base64::encode_block(&record.salt),
+                &base64::encode_block(&record.hash)
+            )
+        })
+        .collect();
+
+    let path = config::state_path(&PASSWORD_HISTORY_FILE);
+    let temporary_path = format!("{}.new", &path);
+    fs::write(&temporary_path, &contents)
+        .with_context(|| "Failed to write password history file")?;
+    fs::set_permissions(&temporary_path, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&temporary_path, &path).with_context(|| "Failed to write password history file")?;
+
+    Ok(())
+}
+
+fn hash(password: &str, salt: &[u8]) -> Result<Vec<u8>> {
+    let mut hash = vec![0; HASH_LENGTH];
+    pbkdf2_hmac(
+        password.as_bytes(),
+        &salt,
+        PBKDF2_ITERATIONS,
+        MessageDigest::sha256(),
+        &mut hash,
+    )
+    .with_context(|| "Failed to hash password")?;
+
+    Ok(hash)
+}
+
+/// Whether the password is one of the user's last `password_history_length` passwords. The password of
+/// storage in disabled mode is shared by every such user, and is never considered reused.
+pub fn is_reused(user: &str, password: &str) -> Result<bool> {
+    let length = Config::read()?.password_history_length;
+    if length == 0 || password == DISABLED_MODE_PASSWORD {
+        return Ok(false);
+    }
+
+    let records: Vec<HistoryRecord> = read_records()?
+        .into_iter()
+        .filter(|record| record.user == user)
+        .collect();
+    // The history may be longer than allowed now, if the setting was lowered
+    for record in records.iter().rev().take(length) {
+        let candidate = hash(&password, &record.salt)?;
+        if candidate.len() == record.hash.len() && memcmp::eq(&candidate, &record.hash) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Keeps the user's last `password_history_length` passwords only
+pub fn record(user: &str, password: &str) -> Result<()> {
+    let length = Config::read()?.password_history_length;
+    if length == 0 || password == DISABLED_MODE_PASSWORD {
+        return Ok(());
+    }
+
+    let mut salt = vec![0; SALT_LENGTH];
+    rand_bytes(&mut salt)?;
+    let hash = hash(&password, &salt)?;
+
+    let mut records = read_records()?;
+    records.push(HistoryRecord {
+        user: user.to_string(),
+        salt,
+        hash,
+    });
+    let count = records.iter().filter(|record| record.user == user).count();
+    let mut excess = count.saturating_sub(length);
+    // Records are in the order they were added: the oldest ones go first
+    records.retain(|record| {
+        if record.user == user && excess > 0 {
+            excess -= 1;
+            return false;
+        }
+        true
+    });
+    info!("Recorded new password of user '{}' in history", &user);
+
+    write_records(&records)
+}
+
+pub fn remove(user: &str) -> Result<()> {
+    let mut records = read_records()?;
+    let count = records.len();
+    records.retain(|record| record.user != user);
+    if records.len() != count {
+        info!("Removing password history of user '{}'", &user);
+        write_records(&records)?;
+    }
+
+    Ok(())
+}
+
+/// UNIX timestamp of the day the user's password was last changed, from the shadow file. None if unknown.
+pub fn last_changed(user: &str) -> Result<Option<u64>> {
+    let contents = fs::read_to_string(&format!("{}/etc/shadow", &OVERLAY_MOUNTPOINT))
+        .with_context(|| "Failed to read shadow file from overlay filesystem")?;
+
+    Ok(contents
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<&str>>())
+        .find(|fields| fields.first() == Some(&user))
+        .and_then(|fields| fields.get(2)?.parse::<u64>().ok())
+        // Zero forces a change at next login, and says nothing about the password's age
+        .filter(|days| *days > 0)
+        .map(|days| days * SECONDS_PER_DAY))
+}
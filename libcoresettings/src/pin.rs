@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use openssl::{base64, hash::MessageDigest, memcmp, pkcs5::pbkdf2_hmac, rand::rand_bytes};
+use std::{fs, os::unix::fs::PermissionsExt, time::Duration};
+
+use crate::{config, lockout, users};
+
+const PINS_FILE: &str = "pins";
+pub const MIN_PIN_LENGTH: usize = 4;
+pub const MAX_PIN_LENGTH: usize = 12;
+/// Wrong PINs in a row after which the PIN is erased, and the full password is required again
+pub const MAX_PIN_FAILURES: u32 = 5;
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+// PINs have little entropy: a slow hash is what keeps the file from being brute-forced in seconds
+const PBKDF2_ITERATIONS: usize = 600_000;
+
+pub enum PinStatus {
+    Success,
+    Failure {
+        remaining_attempts: u32,
+    },
+    /// Too many wrong PINs: the PIN was erased
+    Erased,
+    NotSet,
+    LockedOut {
+        retry_after: Duration,
+    },
+}
+
+struct PinRecord {
+    user: String,
+    failures: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+fn read_records() -> Result<Vec<PinRecord>> {
+    let path = config::state_path(&PINS_FILE);
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(&path).with_context(|| "Failed to read PINs file")?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            // Records hold hashes: do not log them
+            if fields.len() != 4 {
+                warn!("Ignoring malformed PIN record");
+                return None;
+            }
+
+            Some(PinRecord {
+                user: fields[0].to_string(),
+                failures: fields[1].parse().ok()?,
+                salt: base64::decode_block(&fields[2]).ok()?,
+                hash: base64::decode_block(&fields[3]).ok()?,
+            })
+        })
+        .collect())
+}
+
+fn write_records(records: &[PinRecord]) -> Result<()> {
+    config::create_state_dir()?;
+    let contents: String = records
+        .iter()
+        .map(|record| {
+            format!(
+                "{}:{}:{}:{}\n",
+                &record.user,
+                &record.failures,
+                &base64::encode_block(&record.salt),
+                &base64::encode_block(&record.hash)
+            )
+        })
+        .collect();
+
+    let path = config::state_path(&PINS_FILE);
+    let temporary_path = format!("{}.new", &path);
+    fs::write(&temporary_path, &contents).with_context(|| "Failed to write PINs file")?;
+    fs::set_permissions(&temporary_path, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&temporary_path, &path).with_context(|| "Failed to write PINs file")?;
+
+    Ok(())
+}
+
+fn hash(pin: &str, salt: &[u8]) -> Result<Vec<u8>> {
+    let mut hash = vec![0; HASH_LENGTH];
+    pbkdf2_hmac(
+        pin.as_bytes(),
+        &salt,
+        PBKDF2_ITERATIONS,
+        MessageDigest::sha256(),
+        &mut hash,
+    )
+    .with_context(|| "Failed to hash PIN")?;
+
+    Ok(hash)
+}
+
+pub fn validate(pin: &str) -> Result<()> {
+    if pin.len() < MIN_PIN_LENGTH || pin.len() > MAX_PIN_LENGTH {
+        return Err(anyhow::anyhow!(
+            "PIN must be {} to {} digits long",
+            MIN_PIN_LENGTH,
+            MAX_PIN_LENGTH
+        ));
+    }
+    if !pin.chars().all(|character| character.is_ascii_digit()) {
+        return Err(anyhow::anyhow!("PIN must only contain digits"));
+    }
+
+    Ok(())
+}
+
+pub fn is_set(user: &str) -> bool {
+    read_records()
+        .map(|records| records.iter().any(|record| record.user == user))
+        .unwrap_or(false)
+}
+
+/// Sets the user's PIN, proving who they are with their full password. The PIN never unlocks storage.
+pub fn set(user: &str, password: &str, pin: &str) -> Result<()> {
+    validate(&pin)?;
+    users::change_user_password(None, &user, &password, None)
+        .with_context(|| "Failed to verify password")?;
+    info!("Setting PIN of user '{}'", &user);
+
+    let mut salt = vec![0; SALT_LENGTH];
+    rand_bytes(&mut salt)?;
+    let hash = hash(&pin, &salt)?;
+
+    let mut records: Vec<PinRecord> = read_records()?
+        .into_iter()
+        .filter(|record| record.user != user)
+        .collect();
+    records.push(PinRecord {
+        user: user.to_string(),
+        failures: 0,
+        salt,
+        hash,
+    });
+
+    write_records(&records)
+}
+
+pub fn remove(user: &str) -> Result<()> {
+    let mut records = read_records()?;
+    let count = records.len();
+    records.retain(|record| record.user != user);
+    if records.len() != count {
+        info!("Removing PIN of user '{}'", &user);
+        write_records(&records)?;
+    }
+
+    Ok(())
+}
+
+/// Failures are counted separately from passwords, and the PIN is erased after `MAX_PIN_FAILURES` of them.
+/// A user locked out after wrong passwords cannot use their PIN either.
+pub fn verify(user: &str, pin: &str) -> Result<PinStatus> {
+    if let Some(retry_after) = lockout::retry_after(&user)? {
+        return Ok(PinStatus::LockedOut { retry_after });
+    }
+
+    let mut records = read_records()?;
+    let Some(record) = records.iter_mut().find(|record| record.user == user) else {
+        return Ok(PinStatus::NotSet);
+    };
+
+    let candidate = hash(&pin, &record.salt)?;
+    if candidate.len() == record.hash.len() && memcmp::eq(&candidate, &record.hash) {
+        if record.failures > 0 {
+            record.failures = 0;
+            write_records(&records)?;
+        }
+        return Ok(PinStatus::Success);
+    }
+
+    record.failures = record.failures.saturating_add(1);
+    let failures = record.failures;
+    warn!(
+        "Recorded wrong PIN attempt {} for user '{}'",
+        &failures, &user
+    );
+    if failures >= MAX_PIN_FAILURES {
+        warn!("Too many wrong PINs for user '{}': erasing PIN", &user);
+        records.retain(|record| record.user != user);
+        write_records(&records)?;
+        return Ok(PinStatus::Erased);
+    }
+    write_records(&records)?;
+    let remaining_attempts = MAX_PIN_FAILURES - failures;
+
+    Ok(PinStatus::Failure { remaining_attempts })
+}
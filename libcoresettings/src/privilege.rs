@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use libqinit::{OVERLAY_MOUNTPOINT, rootfs::run_chroot_command};
+use log::info;
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use crate::{
+    config::{Config, PrivilegeEscalation},
+    groups, restrictions, users,
+};
+
+const SUDOERS_DROP_IN_FILE: &str = "/etc/sudoers.d/core-settings";
+// sudo skips files containing a dot in sudoers.d, so this one is never picked up half-written
+const SUDOERS_DROP_IN_TEMPORARY_FILE: &str = "/etc/sudoers.d/core-settings.new";
+const VISUDO_BINARY: &str = "/usr/sbin/visudo";
+const DOAS_CONFIG_FILE: &str = "/etc/doas.conf";
+const DOAS_BINARY: &str = "/usr/bin/doas";
+const DOAS_BLOCK_BEGIN: &str = "# BEGIN core-settings";
+const DOAS_BLOCK_END: &str = "# END core-settings";
+
+fn overlay_path(path: &str) -> String {
+    format!("{}/{}", &OVERLAY_MOUNTPOINT, &path)
+}
+
+fn write_sudoers_drop_in(admin_groups: &[String]) -> Result<()> {
+    let mut contents = String::from("# Managed by Core Settings: do not edit\n");
+    for group in admin_groups {
+        contents.push_str(&format!("%{} ALL=(ALL:ALL) ALL\n", &group));
+    }
+
+    let temporary_path = overlay_path(&SUDOERS_DROP_IN_TEMPORARY_FILE);
+    fs::create_dir_all(&overlay_path("/etc/sudoers.d"))?;
+    fs::write(&temporary_path, &contents)
+        .with_context(|| "Failed to write sudoers drop-in file")?;
+    fs::set_permissions(&temporary_path, fs::Permissions::from_mode(0o440))?;
+
+    if fs::exists(&overlay_path(&VISUDO_BINARY))? {
+        if let Err(e) = run_chroot_command(&[
+            &VISUDO_BINARY,
+            "-c",
+            "-q",
+            "-f",
+            &SUDOERS_DROP_IN_TEMPORARY_FILE,
+        ]) {
+            fs::remove_file(&temporary_path)?;
+            return Err(e).with_context(|| "Generated sudoers drop-in file is invalid");
+        }
+    }
+
+    fs::rename(&temporary_path, &overlay_path(&SUDOERS_DROP_IN_FILE))
+        .with_context(|| "Failed to install sudoers drop-in file")?;
+
+    Ok(())
+}
+
+fn remove_sudoers_drop_in() -> Result<()> {
+    let path = overlay_path(&SUDOERS_DROP_IN_FILE);
+    if fs::exists(&path)? {
+        fs::remove_file(&path).with_context(|| "Failed to remove sudoers drop-in file")?;
+    }
+
+    Ok(())
+}
+
+/// doas has no drop-in directory, so we manage a delimited block inside of its configuration file
+fn write_doas_block(admin_groups: Option<&[String]>) -> Result<()> {
+    let path = overlay_path(&DOAS_CONFIG_FILE);
+    let existing_contents = if fs::exists(&path)? {
+        fs::read_to_string(&path).with_context(|| "Failed to read doas configuration file")?
+    } else if admin_groups.is_none() {
+        return Ok(());
+    } else {
+        String::new()
+    };
+
+    let mut contents = String::new();
+    let mut in_block = false;
+    for line in existing_contents.lines() {
+        if line == DOAS_BLOCK_BEGIN {
+            in_block = true;
+        } else if line == DOAS_BLOCK_END {
+            in_block = false;
+        } else if !in_block {
+            contents.push_str(&format!("{}\n", &line));
+        }
+    }
+
+    if let Some(admin_groups) = admin_groups {
+        contents.push_str(&format!("{}\n", &DOAS_BLOCK_BEGIN));
+        for group in admin_groups {
+            contents.push_str(&format!("permit persist :{}\n", &group));
+        }
+        contents.push_str(&format!("{}\n", &DOAS_BLOCK_END));
+    }
+
+    if contents == existing_contents {
+        return Ok(());
+    }
+
+    fs::write(&path, &contents).with_context(|| "Failed to write doas configuration file")?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    if admin_groups.is_some() && fs::exists(&overlay_path(&DOAS_BINARY))? {
+        if let Err(e) = run_chroot_command(&[&DOAS_BINARY, "-C", &DOAS_CONFIG_FILE]) {
+            fs::write(&path, &existing_contents)?;
+            return Err(e).with_context(|| "Generated doas configuration is invalid");
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure that members of the administrator group(s) can actually escalate privileges with the configured tool
+pub fn apply(config: &Config) -> Result<()> {
+    info!(
+        "Applying privilege escalation policy '{:?}' for group(s) {:?}",
+        &config.privilege_escalation, &config.admin_groups
+    );
+
+    match config.privilege_escalation {
+        PrivilegeEscalation::None => {
+            remove_sudoers_drop_in()?;
+            write_doas_block(None)?;
+        }
+        PrivilegeEscalation::Sudo => {
+            write_sudoers_drop_in(&config.admin_groups)?;
+            write_doas_block(None)?;
+        }
+        PrivilegeEscalation::Doas => {
+            remove_sudoers_drop_in()?;
+            write_doas_block(Some(&config.admin_groups))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn update(config: &Config, previous_config: &Config) -> Result<()> {
+    config.write()?;
+    if let Err(e) = apply(&config) {
+        previous_config.write()?;
+        apply(&previous_config)?;
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+pub fn set_privilege_escalation(privilege_escalation: PrivilegeEscalation) -> Result<()> {
+    let previous_config = Config::read()?;
+    let mut config = previous_config.clone();
+    config.privilege_escalation = privilege_escalation;
+
+    update(&config, &previous_config)
+}
+
+pub fn set_admin_group(group: &str, admin: bool) -> Result<()> {
+    let members = groups::members(&groups::get(&group)?)?;
+    let previous_config = Config::read()?;
+    let mut config = previous_config.clone();
+
+    if admin == config.admin_groups.iter().any(|entry| entry == group) {
+        info!(
+            "Group '{}' administrator status is already {}",
+            &group, &admin
+        );
+        return Ok(());
+    }
+
+    if admin {
+        // Would make every member an administrator, which restricted users cannot be
+        if let Some(member) = members
+            .iter()
+            .find(|member| restrictions::is_restricted(&member))
+        {
+            return Err(anyhow::anyhow!(
+                "User '{}' of group '{}' is restricted: lift their restrictions first",
+                &member,
+                &group
+            ));
+        }
+        config.admin_groups.push(group.to_string());
+    } else {
+        config.admin_groups.retain(|entry| entry != group);
+        if config.admin_groups.is_empty() {
+            return Err(anyhow::anyhow!(
+                "At least one administrator group is required"
+            ));
+        }
+    }
+
+    update(&config, &previous_config)?;
+    if users::count_admin_users()? == 0 {
+        update(&previous_config, &config)?;
+        return Err(anyhow::anyhow!("At least one administrator is required"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use libqinit::OVERLAY_MOUNTPOINT;
+use log::warn;
+use std::fs;
+
+use crate::skeleton;
+
+/// One `<name>.conf` file per profile, in the same `key = value` format as the configuration file:
+///
+/// ```text
+/// description = Reading only
+/// groups = video,audio
+/// admin = false
+/// skeleton = reader
+/// encryption = true
+/// shell = /bin/sh
+/// quota_mib = 2048
+/// ```
+pub const PROFILES_DIR: &str = "/etc/core-settings/profiles";
+const PROFILE_FILE_EXTENSION: &str = "conf";
+const BYTES_PER_MIB: u64 = 1024 * 1024;
+
+/// Template for new accounts. Unset fields leave the choice to whoever creates the account.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub description: String,
+    /// Supplementary groups, on top of administrator groups
+    pub groups: Vec<String>,
+    pub admin: Option<bool>,
+    /// Name of a skeleton in `skeleton::SKELETON_PROFILES_DIR`
+    pub skeleton: Option<String>,
+    /// Whether new storage is encrypted, or created in disabled mode
+    pub encryption: Option<bool>,
+    pub shell: Option<String>,
+    pub quota_bytes: Option<u64>,
+}
+
+fn profiles_dir_path() -> String {
+    format!("{}/{}", &OVERLAY_MOUNTPOINT, &PROFILES_DIR)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parse(name: &str, contents: &str) -> Profile {
+    let mut profile = Profile {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            warn!("Ignoring malformed line '{}' in profile '{}'", &line, &name);
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        match key {
+            "description" => profile.description = value.to_string(),
+            "groups" => {
+                profile.groups = value
+                    .split(',')
+                    .map(|group| group.trim().to_string())
+                    .filter(|group| !group.is_empty())
+                    .collect()
+            }
+            "admin" => {
+                profile.admin = parse_bool(&value);
+                if profile.admin.is_none() {
+                    warn!(
+                        "Invalid administrator setting '{}' in profile '{}'",
+                        &value, &name
+                    );
+                }
+            }
+            "skeleton" => match skeleton::profile_dir(&value) {
+                Ok(_) => profile.skeleton = Some(value.to_string()),
+                Err(e) => warn!("{} in profile '{}'", e, &name),
+            },
+            "encryption" => {
+                profile.encryption = parse_bool(&value);
+                if profile.encryption.is_none() {
+                    warn!(
+                        "Invalid encryption setting '{}' in profile '{}'",
+                        &value, &name
+                    );
+                }
+            }
+            "shell" => profile.shell = Some(value.to_string()),
+            "quota_mib" => match value.parse::<u64>() {
+                Ok(mib) if mib > 0 => profile.quota_bytes = Some(mib * BYTES_PER_MIB),
+                _ => warn!("Invalid quota '{}' in profile '{}'", &value, &name),
+            },
+            _ => warn!("Ignoring unknown key '{}' in profile '{}'", &key, &name),
+        }
+    }
+
+    profile
+}
+
+/// Profiles defined in the overlay, sorted by name
+pub fn list() -> Result<Vec<Profile>> {
+    let path = profiles_dir_path();
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+
+    let mut profiles = Vec::new();
+    for entry in fs::read_dir(&path).with_context(|| "Failed to read profiles directory")? {
+        let entry_path = entry?.path();
+        if entry_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            != Some(PROFILE_FILE_EXTENSION)
+        {
+            continue;
+        }
+        let Some(name) = entry_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+        else {
+            continue;
+        };
+        let contents = fs::read_to_string(&entry_path)
+            .with_context(|| format!("Failed to read profile '{}'", &name))?;
+        profiles.push(parse(&name, &contents));
+    }
+    profiles.sort_by(|first, second| first.name.cmp(&second.name));
+
+    Ok(profiles)
+}
+
+pub fn get(name: &str) -> Result<Profile> {
+    list()?
+        .into_iter()
+        .find(|profile| profile.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Profile '{}' does not exist", &name))
+}
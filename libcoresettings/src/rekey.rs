@@ -0,0 +1,374 @@
+use anyhow::{Context, Result};
+use libqinit::{storage_encryption, system};
+use log::{error, info};
+use std::{
+    fs,
+    io::Read,
+    os::unix::fs::{MetadataExt, chown},
+    path::PathBuf,
+};
+
+use crate::{
+    activity, escrow, fsutil, gocryptfs, lockout,
+    users::{encrypted_storage_path, home_dir_path},
+};
+
+/// scrypt cost (2^17) used when upgrading volumes: one step above gocryptfs' default
+pub const UPGRADED_SCRYPT_LOG_N: u8 = 17;
+// gocryptfs adds an 18-byte header to each file and 32 bytes to each 4 KiB block
+const ENCRYPTION_OVERHEAD_PERCENT: u64 = 2;
+const FREE_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+// Files tried when checking a master key, at most
+const MAX_VERIFIED_FILES: usize = 16;
+const VERIFIED_BLOCK_SIZE: usize = 4096;
+
+/// Every name `scratch_mountpoint` is used with
+pub(crate) const SCRATCH_MOUNTPOINT_NAMES: &[&str] = &["old", "new", "fsck", "recovery"];
+
+pub(crate) fn scratch_mountpoint(user: &str, name: &str) -> String {
+    format!("/run/core-settings-{}-{}", &user, &name)
+}
+
+pub fn needs_scrypt_upgrade(user: &str) -> Result<bool> {
+    let config = gocryptfs::read_config(&encrypted_storage_path(&user))?;
+
+    Ok(config.scrypt.n < 1 << UPGRADED_SCRYPT_LOG_N)
+}
+
+pub(crate) fn verify_password(user: &str, password: &str) -> Result<()> {
+    lockout::check(&user)?;
+
+    if let Err(e) = gocryptfs::dump_master_key(&encrypted_storage_path(&user), &password) {
+        if let Err(e) = lockout::record_failure(&user) {
+            error!("Failed to record failed authentication attempt: {}", &e);
+        }
+        return Err(e).with_context(|| "Provided password was incorrect");
+    }
+    if let Err(e) = lockout::reset(&user) {
+        error!("Failed to reset failed authentication attempts: {}", &e);
+    }
+
+    Ok(())
+}
+
+// gocryptfs authenticates file contents: no other master key can read them back. Names which fail to decrypt
+// are left out of listings.
+fn read_some_file(mountpoint: &str) -> Result<()> {
+    let mut dirs = vec![PathBuf::from(&mountpoint)];
+    let mut block = vec![0; VERIFIED_BLOCK_SIZE];
+    let mut tried = 0;
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if metadata.is_file() && metadata.len() > 0 {
+                if fs::File::open(entry.path())
+                    .and_then(|mut file| file.read(&mut block))
+                    .is_ok()
+                {
+                    return Ok(());
+                }
+                tried += 1;
+                if tried == MAX_VERIFIED_FILES {
+                    break;
+                }
+            }
+        }
+        if tried == MAX_VERIFIED_FILES {
+            break;
+        }
+    }
+
+    if tried == 0 {
+        return Err(anyhow::anyhow!(
+            "No file of the encrypted storage could be read: the storage is empty, or the master key is wrong"
+        ));
+    }
+    Err(anyhow::anyhow!(
+        "Files of the encrypted storage could not be decrypted"
+    ))
+}
+
+/// Checks a master key against a user's encrypted storage without writing to it, counting failures like
+/// wrong passwords
+pub(crate) fn verify_master_key(user: &str, master_key: &[u8]) -> Result<()> {
+    lockout::check(&user)?;
+
+    let mountpoint = scratch_mountpoint(&user, "recovery");
+    fs::create_dir_all(&mountpoint)?;
+    let result =
+        gocryptfs::mount_with_master_key(&encrypted_storage_path(&user), &mountpoint, &master_key)
+            .and_then(|_| read_some_file(&mountpoint));
+    unmount(&mountpoint);
+
+    if let Err(e) = result {
+        if let Err(e) = lockout::record_failure(&user) {
+            error!("Failed to record failed authentication attempt: {}", &e);
+        }
+        return Err(e).with_context(|| "Recovery code does not match user's encrypted storage");
+    }
+    if let Err(e) = lockout::reset(&user) {
+        error!("Failed to reset failed authentication attempts: {}", &e);
+    }
+
+    Ok(())
+}
+
+/// Raises the cost of deriving the key wrapping the master key from the user's password. Data is untouched.
+pub fn upgrade_scrypt_cost(user: &str, password: &str) -> Result<()> {
+    info!("Upgrading scrypt cost of user '{}'", &user);
+    verify_password(&user, &password)?;
+
+    let path = encrypted_storage_path(&user);
+    gocryptfs::remove_config_backup(&path)?;
+    gocryptfs::set_scrypt_cost(&path, &password, UPGRADED_SCRYPT_LOG_N)?;
+    // The backup still holds the key wrapped with the weaker cost
+    gocryptfs::remove_config_backup(&path)
+}
+
+pub(crate) fn unmount(mountpoint: &str) {
+    if let Ok(true) = system::is_mountpoint(&mountpoint) {
+        if let Err(e) = system::run_command("/bin/umount", &[&mountpoint]) {
+            error!("Failed to unmount '{}': {}", &mountpoint, e);
+        }
+    }
+    let _ = fs::remove_dir(&mountpoint);
+}
+
+/// Copies a directory into a freshly initialized volume with a strong scrypt cost, then checks the copy
+fn fill_new_volume(
+    user: &str,
+    source_dir: &str,
+    new_path: &str,
+    password: &str,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    let new_mountpoint = scratch_mountpoint(&user, "new");
+    fs::create_dir_all(&new_mountpoint)?;
+
+    let result = (|| -> Result<()> {
+        let total = fsutil::dir_size(&source_dir)?;
+        let required = total + total * ENCRYPTION_OVERHEAD_PERCENT / 100 + FREE_SPACE_MARGIN;
+        let available = fsutil::free_space(&libqinit::MAIN_PART_MOUNTPOINT)?;
+        if available < required {
+            return Err(anyhow::anyhow!(
+                "Not enough free space: {} MiB required, {} MiB available",
+                required / 1024 / 1024,
+                available / 1024 / 1024
+            ));
+        }
+
+        gocryptfs::init(&new_path, &password, Some(UPGRADED_SCRYPT_LOG_N))?;
+        gocryptfs::mount(&new_path, &new_mountpoint, &password, false)?;
+        let mut copied = 0;
+        progress(copied, total);
+        fsutil::copy_tree(&source_dir, &new_mountpoint, &mut |length| {
+            copied += length;
+            progress(copied, total);
+        })?;
+
+        // The volume's root directory is the user's home directory itself
+        let root_metadata = fs::metadata(&source_dir)?;
+        chown(
+            &new_mountpoint,
+            Some(root_metadata.uid()),
+            Some(root_metadata.gid()),
+        )?;
+        fs::set_permissions(&new_mountpoint, root_metadata.permissions())?;
+
+        fsutil::verify_tree(&source_dir, &new_mountpoint)
+    })();
+
+    unmount(&new_mountpoint);
+
+    result
+}
+
+fn copy_into_new_volume(
+    user: &str,
+    old_path: &str,
+    old_password: &str,
+    new_path: &str,
+    new_password: &str,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    let old_mountpoint = scratch_mountpoint(&user, "old");
+    fs::create_dir_all(&old_mountpoint)?;
+
+    let result = gocryptfs::mount(&old_path, &old_mountpoint, &old_password, true)
+        .and_then(|_| fill_new_volume(&user, &old_mountpoint, &new_path, &new_password, progress));
+
+    unmount(&old_mountpoint);
+
+    result
+}
+
+/// Copies a user's data into a freshly initialized volume using current gocryptfs feature flags and a stronger
+/// scrypt cost, then swaps it in place of the old one. This changes the master key, so escrowed keys are
+/// renewed and previous recovery codes stop working.
+pub fn migrate(user: &str, password: &str, progress: &mut dyn FnMut(u64, u64)) -> Result<()> {
+    info!(
+        "Migrating encrypted storage of user '{}' to a new volume",
+        &user
+    );
+    activity::ensure_idle(&user)?;
+    verify_password(&user, &password)?;
+
+    let old_path = encrypted_storage_path(&user);
+    let new_path = format!("{}.new", &old_path);
+    if fs::exists(&new_path)? {
+        system::rm_dir_all(&new_path)
+            .with_context(|| "Failed to remove leftovers of a previous migration")?;
+    }
+    fs::create_dir_all(&new_path)?;
+
+    if let Err(e) =
+        copy_into_new_volume(&user, &old_path, &password, &new_path, &password, progress)
+    {
+        let _ = system::rm_dir_all(&new_path);
+        return Err(e);
+    }
+
+    let disabled_mode_file = format!("{}/{}", &old_path, &storage_encryption::DISABLED_MODE_FILE);
+    if fs::exists(&disabled_mode_file)? {
+        fs::File::create(&format!(
+            "{}/{}",
+            &new_path,
+            &storage_encryption::DISABLED_MODE_FILE
+        ))?;
+    }
+
+    replace_storage(&new_path, &old_path)?;
+
+    escrow::remove(&user)?;
+    if let Err(e) = escrow::escrow(&user, &old_path, &password) {
+        error!("Failed to escrow key of user '{}': {}", &user, e);
+    }
+    info!("Migrated encrypted storage of user '{}'", &user);
+
+    Ok(())
+}
+
+enum PlaintextSource {
+    /// gocryptfs volume whose password is `DISABLED_MODE_PASSWORD`
+    DisabledVolume,
+    /// Home directory created without any separate storage
+    PlainHome,
+}
+
+fn plaintext_source(user: &str) -> Result<PlaintextSource> {
+    let storage_path = encrypted_storage_path(&user);
+    if fs::exists(&storage_path)? {
+        if !fs::exists(&format!("{}/{}", &storage_path, &gocryptfs::CONFIG_FILE))? {
+            Err(anyhow::anyhow!(
+                "Storage of user '{}' is not a gocryptfs volume",
+                &user
+            ))
+        } else if fs::exists(&format!(
+            "{}/{}",
+            &storage_path,
+            &storage_encryption::DISABLED_MODE_FILE
+        ))? {
+            Ok(PlaintextSource::DisabledVolume)
+        } else {
+            Err(anyhow::anyhow!(
+                "Storage of user '{}' is already encrypted",
+                &user
+            ))
+        }
+    } else if fs::exists(&home_dir_path(&user))? {
+        Ok(PlaintextSource::PlainHome)
+    } else {
+        Err(anyhow::anyhow!("User '{}' has no home directory", &user))
+    }
+}
+
+/// Atomically swaps the new storage with the one in place, so that there is always a storage at `storage_path`
+fn replace_storage(new_path: &str, storage_path: &str) -> Result<()> {
+    fsutil::exchange(&new_path, &storage_path)
+        .with_context(|| "Failed to move new encrypted storage in place")?;
+    // The previous storage now sits at the temporary path
+    system::rm_dir_all(&new_path).with_context(|| "Failed to remove previous storage")
+}
+
+// Atomically swaps in an empty directory with the same ownership and permissions, to be used as mountpoint.
+// The home directory and the storage live on different filesystems, so this cannot be done in the same step as
+// moving the new storage in place: a crash in between leaves the plaintext data under the new storage's
+// mountpoint, hidden but not removed.
+fn empty_home_dir(user: &str) -> Result<()> {
+    let home_dir_path = home_dir_path(&user);
+    let swap_path = format!("{}.old", &home_dir_path);
+    if fs::exists(&swap_path)? {
+        system::rm_dir_all(&swap_path)
+            .with_context(|| "Failed to remove leftovers of a previous migration")?;
+    }
+    let metadata = fs::metadata(&home_dir_path)?;
+    fs::create_dir(&swap_path)?;
+    chown(&swap_path, Some(metadata.uid()), Some(metadata.gid()))?;
+    fs::set_permissions(&swap_path, metadata.permissions())?;
+
+    fsutil::exchange(&swap_path, &home_dir_path)
+        .with_context(|| "Failed to move plaintext home directory out of the way")?;
+    // The plaintext home directory now sits at the temporary path
+    system::rm_dir_all(&swap_path).with_context(|| "Failed to remove plaintext home directory")
+}
+
+/// Moves a user whose data is not protected (storage in disabled mode, or a plain home directory) to a new
+/// gocryptfs volume protected by `password`. The data is copied and verified before the new volume atomically
+/// takes the place of the old storage. Plain home directories are emptied right after, see `empty_home_dir`.
+pub fn encrypt_plaintext_home(
+    user: &str,
+    password: &str,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    info!("Encrypting home directory of user '{}' in place", &user);
+    activity::ensure_idle(&user)?;
+    let source = plaintext_source(&user)?;
+
+    let storage_path = encrypted_storage_path(&user);
+    let new_path = format!("{}.new", &storage_path);
+    if fs::exists(&new_path)? {
+        system::rm_dir_all(&new_path)
+            .with_context(|| "Failed to remove leftovers of a previous migration")?;
+    }
+    fs::create_dir_all(&new_path)?;
+
+    let result = match source {
+        PlaintextSource::DisabledVolume => copy_into_new_volume(
+            &user,
+            &storage_path,
+            &storage_encryption::DISABLED_MODE_PASSWORD,
+            &new_path,
+            &password,
+            progress,
+        ),
+        PlaintextSource::PlainHome => {
+            fill_new_volume(&user, &home_dir_path(&user), &new_path, &password, progress)
+        }
+    };
+    if let Err(e) = result {
+        let _ = system::rm_dir_all(&new_path);
+        return Err(e);
+    }
+
+    match source {
+        PlaintextSource::DisabledVolume => {
+            replace_storage(&new_path, &storage_path)?;
+        }
+        PlaintextSource::PlainHome => {
+            // There is no storage yet, so this is a single atomic step too
+            fs::rename(&new_path, &storage_path)
+                .with_context(|| "Failed to move new encrypted storage in place")?;
+            empty_home_dir(&user)?;
+        }
+    }
+    info!("Encrypted home directory of user '{}'", &user);
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+use crate::activity::unescape_mount_field;
+
+fn is_removable(device: &str) -> bool {
+    let Some(name) = device.strip_prefix("/dev/") else {
+        return false;
+    };
+
+    let Ok(sysfs_path) = fs::canonicalize(&format!("/sys/class/block/{}", &name)) else {
+        return false;
+    };
+    if sysfs_path.to_string_lossy().contains("/usb") {
+        return true;
+    }
+
+    // Partitions do not have a 'removable' attribute, their parent disk does
+    let disk_path = if sysfs_path.join("partition").exists() {
+        sysfs_path.parent().map(Path::to_path_buf)
+    } else {
+        Some(sysfs_path)
+    };
+
+    disk_path
+        .and_then(|path| fs::read_to_string(path.join("removable")).ok())
+        .map(|removable| removable.trim() == "1")
+        .unwrap_or(false)
+}
+
+/// Mountpoints of removable storage devices (USB drives, SD cards) currently mounted
+pub fn mountpoints() -> Result<Vec<String>> {
+    let mounts = fs::read_to_string("/proc/mounts").with_context(|| "Failed to read mounts")?;
+
+    Ok(mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = unescape_mount_field(fields.next()?);
+            let mountpoint = unescape_mount_field(fields.next()?);
+            if is_removable(&device) {
+                Some(mountpoint)
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+pub fn first_mountpoint() -> Result<String> {
+    mountpoints()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No removable storage found"))
+}
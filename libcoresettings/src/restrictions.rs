@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use libqinit::OVERLAY_MOUNTPOINT;
+use log::{info, warn};
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use crate::users::is_admin;
+
+/// Policy files read by the launcher and the session, one `<user>.conf` per restricted user:
+///
+/// ```text
+/// allowed_apps = reader,notes
+/// daily_limit_minutes = 90
+/// allowed_hours = 8-20
+/// ```
+///
+/// Files are owned by root and world-readable, so that restricted users can read but not change them.
+pub const POLICY_DIR: &str = "/etc/core-settings/restrictions";
+const POLICY_FILE_EXTENSION: &str = "conf";
+const HOURS_PER_DAY: u8 = 24;
+
+/// What a restricted user may do. Enforcement is up to the launcher and the session.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Restrictions {
+    /// Applications the user may launch, as launcher application IDs. Empty allows none.
+    pub allowed_apps: Vec<String>,
+    pub daily_limit_minutes: Option<u32>,
+    /// Local hours `[start, end)` the device may be used in, wrapping past midnight if `end < start`
+    pub allowed_hours: Option<(u8, u8)>,
+}
+
+impl Restrictions {
+    pub fn allows_app(&self, app: &str) -> bool {
+        self.allowed_apps
+            .iter()
+            .any(|allowed_app| allowed_app == app)
+    }
+
+    pub fn allows_hour(&self, hour: u8) -> bool {
+        match self.allowed_hours {
+            None => true,
+            Some((start, end)) if start <= end => hour >= start && hour < end,
+            Some((start, end)) => hour >= start || hour < end,
+        }
+    }
+}
+
+fn policy_dir_path() -> String {
+    format!("{}/{}", &OVERLAY_MOUNTPOINT, &POLICY_DIR)
+}
+
+fn policy_file_path(user: &str) -> String {
+    format!(
+        "{}/{}.{}",
+        &policy_dir_path(),
+        &user,
+        &POLICY_FILE_EXTENSION
+    )
+}
+
+fn parse_hours(value: &str) -> Option<(u8, u8)> {
+    let (start, end) = value.split_once('-')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    if start >= HOURS_PER_DAY || end > HOURS_PER_DAY || start == end {
+        return None;
+    }
+
+    Some((start, end))
+}
+
+pub fn parse(user: &str, contents: &str) -> Restrictions {
+    let mut restrictions = Restrictions::default();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            warn!(
+                "Ignoring malformed line '{}' in restrictions of user '{}'",
+                &line, &user
+            );
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        match key {
+            "allowed_apps" => {
+                restrictions.allowed_apps = value
+                    .split(',')
+                    .map(|app| app.trim().to_string())
+                    .filter(|app| !app.is_empty())
+                    .collect()
+            }
+            "daily_limit_minutes" => match value.parse::<u32>() {
+                Ok(minutes) if minutes > 0 => restrictions.daily_limit_minutes = Some(minutes),
+                _ => warn!(
+                    "Invalid daily limit '{}' in restrictions of user '{}'",
+                    &value, &user
+                ),
+            },
+            "allowed_hours" => {
+                restrictions.allowed_hours = parse_hours(&value);
+                if restrictions.allowed_hours.is_none() {
+                    warn!(
+                        "Invalid allowed hours '{}' in restrictions of user '{}'",
+                        &value, &user
+                    );
+                }
+            }
+            _ => warn!(
+                "Ignoring unknown key '{}' in restrictions of user '{}'",
+                &key, &user
+            ),
+        }
+    }
+
+    restrictions
+}
+
+fn serialize(restrictions: &Restrictions) -> String {
+    let mut contents = format!("allowed_apps = {}\n", restrictions.allowed_apps.join(","));
+    if let Some(minutes) = restrictions.daily_limit_minutes {
+        contents.push_str(&format!("daily_limit_minutes = {}\n", minutes));
+    }
+    if let Some((start, end)) = restrictions.allowed_hours {
+        contents.push_str(&format!("allowed_hours = {}-{}\n", start, end));
+    }
+
+    contents
+}
+
+/// `None` if the user is not restricted
+pub fn get(user: &str) -> Result<Option<Restrictions>> {
+    let path = policy_file_path(&user);
+    if !fs::exists(&path)? {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read restrictions of user '{}'", &user))?;
+    Ok(Some(parse(&user, &contents)))
+}
+
+pub fn is_restricted(user: &str) -> bool {
+    fs::exists(&policy_file_path(&user)).unwrap_or(false)
+}
+
+/// Restricts a user, or updates their restrictions. Administrators cannot be restricted.
+pub fn set(user: &str, restrictions: &Restrictions) -> Result<()> {
+    if is_admin(&user) {
+        return Err(anyhow::anyhow!(
+            "User '{}' is an administrator and cannot be restricted",
+            &user
+        ));
+    }
+    if let Some(app) = restrictions
+        .allowed_apps
+        .iter()
+        .find(|app| app.contains(',') || app.contains('\n'))
+    {
+        return Err(anyhow::anyhow!("Invalid application ID '{}'", &app));
+    }
+    info!("Setting restrictions of user '{}'", &user);
+
+    let dir_path = policy_dir_path();
+    fs::create_dir_all(&dir_path).with_context(|| "Failed to create restrictions directory")?;
+    fs::set_permissions(&dir_path, fs::Permissions::from_mode(0o755))?;
+
+    let path = policy_file_path(&user);
+    let temporary_path = format!("{}.new", &path);
+    fs::write(&temporary_path, &serialize(&restrictions))
+        .with_context(|| "Failed to write restrictions file")?;
+    fs::set_permissions(&temporary_path, fs::Permissions::from_mode(0o644))?;
+    fs::rename(&temporary_path, &path).with_context(|| "Failed to write restrictions file")?;
+
+    Ok(())
+}
+
+/// Lifts all restrictions of a user
+pub fn remove(user: &str) -> Result<()> {
+    let path = policy_file_path(&user);
+    if fs::exists(&path)? {
+        info!("Removing restrictions of user '{}'", &user);
+        fs::remove_file(&path).with_context(|| "Failed to remove restrictions file")?;
+    }
+
+    Ok(())
+}
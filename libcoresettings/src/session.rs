@@ -0,0 +1,100 @@
+use log::info;
+use std::time::{Duration, Instant, SystemTime};
+
+// The monotonic clock does not advance while the device is suspended, the wall clock does
+const SUSPEND_DETECTION_TOLERANCE: Duration = Duration::from_secs(5);
+
+struct Elevation {
+    user: String,
+    unlocked_at: SystemTime,
+    last_activity: Instant,
+    last_activity_wall: SystemTime,
+}
+
+/// Records which administrator unlocked Core Settings and locks it again once idle or after a suspend
+pub struct AdminSession {
+    elevation: Option<Elevation>,
+    idle_timeout: Duration,
+}
+
+impl AdminSession {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            elevation: None,
+            idle_timeout,
+        }
+    }
+
+    pub fn unlock(&mut self, user: &str) {
+        info!("Administrator session unlocked by user '{}'", &user);
+        self.elevation = Some(Elevation {
+            user: user.to_string(),
+            unlocked_at: SystemTime::now(),
+            last_activity: Instant::now(),
+            last_activity_wall: SystemTime::now(),
+        });
+    }
+
+    pub fn lock(&mut self) {
+        if let Some(elevation) = self.elevation.take() {
+            info!("Administrator session of user '{}' locked", &elevation.user);
+        }
+    }
+
+    fn expired(&self, elevation: &Elevation) -> bool {
+        let idle = elevation.last_activity.elapsed();
+        if idle >= self.idle_timeout {
+            info!("Administrator session idle for {:?}", &idle);
+            return true;
+        }
+
+        let wall_idle = SystemTime::now()
+            .duration_since(elevation.last_activity_wall)
+            .unwrap_or(Duration::ZERO);
+        if wall_idle > idle + SUSPEND_DETECTION_TOLERANCE {
+            info!("System was suspended during administrator session");
+            return true;
+        }
+
+        false
+    }
+
+    /// Locks the session if it expired in the meantime
+    pub fn is_unlocked(&mut self) -> bool {
+        let expired = match &self.elevation {
+            Some(elevation) => self.expired(&elevation),
+            None => return false,
+        };
+        if expired {
+            self.lock();
+        }
+
+        !expired
+    }
+
+    /// Extends the session; returns false if it is not unlocked (anymore)
+    pub fn touch(&mut self) -> bool {
+        if !self.is_unlocked() {
+            return false;
+        }
+
+        if let Some(elevation) = self.elevation.as_mut() {
+            elevation.last_activity = Instant::now();
+            elevation.last_activity_wall = SystemTime::now();
+        }
+
+        true
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        self.elevation
+            .as_ref()
+            .map(|elevation| elevation.user.as_str())
+    }
+
+    pub fn unlocked_at(&self) -> Option<SystemTime> {
+        self.elevation
+            .as_ref()
+            .map(|elevation| elevation.unlocked_at)
+    }
+}
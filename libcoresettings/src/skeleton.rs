@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use libqinit::OVERLAY_MOUNTPOINT;
+use log::info;
+use std::{fs, os::unix::fs::lchown};
+
+use crate::{fsutil, groups, users::home_dir_path};
+
+/// Files every new home directory starts with
+pub const SKELETON_DIR: &str = "/etc/skel";
+/// Role-specific skeletons, as `<SKELETON_PROFILES_DIR>/<profile>`, copied over the default one
+pub const SKELETON_PROFILES_DIR: &str = "/etc/skel.d";
+
+fn overlay_path(path: &str) -> String {
+    format!("{}/{}", &OVERLAY_MOUNTPOINT, &path)
+}
+
+pub fn profile_dir(profile: &str) -> Result<String> {
+    if profile.is_empty() || profile.contains('/') || profile.starts_with('.') {
+        return Err(anyhow::anyhow!("Invalid skeleton profile '{}'", &profile));
+    }
+
+    Ok(format!("{}/{}", &SKELETON_PROFILES_DIR, &profile))
+}
+
+/// Skeleton profiles available in the overlay
+pub fn profiles() -> Result<Vec<String>> {
+    let path = overlay_path(&SKELETON_PROFILES_DIR);
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+
+    let mut profiles: Vec<String> = fs::read_dir(&path)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if entry.file_type().ok()?.is_dir() {
+                Some(entry.file_name().to_string_lossy().to_string())
+            } else {
+                None
+            }
+        })
+        .collect();
+    profiles.sort();
+
+    Ok(profiles)
+}
+
+/// Copies the skeleton, then the profile's one if any, into a user's home directory, which must be mounted.
+/// Everything, including the home directory itself, ends up owned by the user and their primary group.
+pub fn populate(user: &str, profile: Option<&str>) -> Result<()> {
+    let uid = groups::user_id(&user)?
+        .ok_or_else(|| anyhow::anyhow!("User '{}' does not exist", &user))?;
+    let gid = groups::primary_group_id(&user)?
+        .ok_or_else(|| anyhow::anyhow!("User '{}' has no primary group", &user))?;
+    let home_dir_path = home_dir_path(&user);
+
+    let mut skeletons = vec![SKELETON_DIR.to_string()];
+    if let Some(profile) = profile {
+        let profile_dir = profile_dir(&profile)?;
+        if !fs::exists(&overlay_path(&profile_dir))? {
+            return Err(anyhow::anyhow!(
+                "Skeleton profile '{}' does not exist",
+                &profile
+            ));
+        }
+        skeletons.push(profile_dir);
+    }
+
+    for skeleton in &skeletons {
+        let path = overlay_path(&skeleton);
+        if !fs::exists(&path)? {
+            continue;
+        }
+        info!("Copying skeleton '{}' for user '{}'", &skeleton, &user);
+        fsutil::copy_tree_as(&path, &home_dir_path, Some((uid, gid)), &mut |_| {})
+            .with_context(|| format!("Failed to copy skeleton '{}'", &skeleton))?;
+    }
+    lchown(&home_dir_path, Some(uid), Some(gid))
+        .with_context(|| "Failed to set filesystem permissions")?;
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use anyhow::{Context, Result};
+use libqinit::{MAIN_PART_MOUNTPOINT, system};
+use log::{info, warn};
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use crate::{
+    config, fsutil,
+    users::{encrypted_storage_path, home_dir_path},
+};
+
+const QUOTAS_FILE: &str = "quotas";
+
+pub struct UserUsage {
+    /// Space taken by the `.<user>` storage directory, encrypted or not
+    pub storage_bytes: u64,
+    /// Size of the decrypted contents, only known while the home directory is mounted
+    pub plaintext_bytes: Option<u64>,
+    pub quota_bytes: Option<u64>,
+}
+
+impl UserUsage {
+    pub fn over_quota(&self) -> bool {
+        self.quota_bytes
+            .is_some_and(|quota_bytes| self.storage_bytes > quota_bytes)
+    }
+}
+
+pub struct PartitionUsage {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+}
+
+impl PartitionUsage {
+    pub fn used_bytes(&self) -> u64 {
+        self.total_bytes.saturating_sub(self.free_bytes)
+    }
+}
+
+fn read_quotas() -> Result<Vec<(String, u64)>> {
+    let path = config::state_path(&QUOTAS_FILE);
+    if !fs::exists(&path)? {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(&path).with_context(|| "Failed to read quotas file")?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let quota = line
+                .split_once(':')
+                .and_then(|(user, bytes)| Some((user.to_string(), bytes.parse().ok()?)));
+            if quota.is_none() {
+                warn!("Ignoring malformed quota record '{}'", &line);
+            }
+
+            quota
+        })
+        .collect())
+}
+
+fn write_quotas(quotas: &[(String, u64)]) -> Result<()> {
+    config::create_state_dir()?;
+    let contents: String = quotas
+        .iter()
+        .map(|(user, bytes)| format!("{}:{}\n", &user, &bytes))
+        .collect();
+
+    let path = config::state_path(&QUOTAS_FILE);
+    let temporary_path = format!("{}.new", &path);
+    fs::write(&temporary_path, &contents).with_context(|| "Failed to write quotas file")?;
+    fs::set_permissions(&temporary_path, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&temporary_path, &path).with_context(|| "Failed to write quotas file")?;
+
+    Ok(())
+}
+
+pub fn quota(user: &str) -> Result<Option<u64>> {
+    Ok(read_quotas()?
+        .into_iter()
+        .find(|(quota_user, _)| quota_user == user)
+        .map(|(_, bytes)| bytes))
+}
+
+/// Soft quotas are only reported: nothing prevents a user from writing past theirs
+pub fn set_quota(user: &str, bytes: Option<u64>) -> Result<()> {
+    match bytes {
+        Some(bytes) => info!(
+            "Setting quota of user '{}' to {}",
+            &user,
+            fsutil::format_size(bytes)
+        ),
+        None => info!("Removing quota of user '{}'", &user),
+    }
+    let mut quotas: Vec<(String, u64)> = read_quotas()?
+        .into_iter()
+        .filter(|(quota_user, _)| quota_user != user)
+        .collect();
+    if let Some(bytes) = bytes {
+        quotas.push((user.to_string(), bytes));
+    }
+
+    write_quotas(&quotas)
+}
+
+pub fn remove(user: &str) -> Result<()> {
+    set_quota(&user, None)
+}
+
+pub fn user_usage(user: &str) -> Result<UserUsage> {
+    let home_dir_path = home_dir_path(&user);
+    let plaintext_bytes = if system::is_mountpoint(&home_dir_path)? {
+        Some(fsutil::dir_size(&home_dir_path)?)
+    } else {
+        None
+    };
+
+    Ok(UserUsage {
+        storage_bytes: fsutil::dir_size(&encrypted_storage_path(&user))?,
+        plaintext_bytes,
+        quota_bytes: quota(&user)?,
+    })
+}
+
+pub fn partition_usage() -> Result<PartitionUsage> {
+    Ok(PartitionUsage {
+        total_bytes: fsutil::total_space(&MAIN_PART_MOUNTPOINT)?,
+        free_bytes: fsutil::free_space(&MAIN_PART_MOUNTPOINT)?,
+    })
+}
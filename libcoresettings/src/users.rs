@@ -1,23 +1,45 @@
 use anyhow::{Context, Result};
-use libqinit::{
-    boot_config::BootConfig, rootfs::run_chroot_command, storage_encryption::GOCRYPTFS_BINARY,
-};
-use log::{error, info};
+use libqinit::{boot_config::BootConfig, rootfs::run_chroot_command};
+use log::{error, info, warn};
 use std::{
     fs,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use libqinit::{OVERLAY_MOUNTPOINT, SYSTEM_HOME_DIR, rootfs, storage_encryption, system};
 use openssl::pkey::PKey;
 use openssl::pkey::Public;
 
-const ADMIN_GROUP: &str = "wheel";
+use crate::{
+    activity, autologin,
+    config::Config,
+    encryption::{self, EncryptionStatus},
+    escrow, gocryptfs, groups,
+    lockout::{self, LockedOut},
+    loginscreen, password_history, pin,
+    profiles::Profile,
+    rekey, restrictions, skeleton, usage, wipe,
+};
 
 pub enum AdminLoginStatus {
     Success,
     Failure,
     NotAdmin,
+    LockedOut { retry_after: Duration },
+}
+
+pub(crate) fn encrypted_storage_path(user: &str) -> String {
+    format!(
+        "{}/{}/.{}",
+        &libqinit::MAIN_PART_MOUNTPOINT,
+        &libqinit::SYSTEM_HOME_DIR,
+        &user
+    )
+}
+
+pub(crate) fn home_dir_path(user: &str) -> String {
+    format!("{}/{}/{}", &OVERLAY_MOUNTPOINT, &SYSTEM_HOME_DIR, &user)
 }
 
 pub fn change_encryption_password(
@@ -25,67 +47,116 @@ pub fn change_encryption_password(
     old_password: &str,
     new_password: &str,
 ) -> Result<()> {
-    system::run_command(
-        "/bin/sh",
-        &[
-            "-c",
-            &format!(
-                "printf '{}\n{}' | {} -passwd {}/{}/.{}",
-                &old_password,
-                &new_password,
-                &storage_encryption::GOCRYPTFS_BINARY,
-                &libqinit::MAIN_PART_MOUNTPOINT,
-                &libqinit::SYSTEM_HOME_DIR,
-                &user
-            ),
-        ],
-    )
-    .with_context(|| {
-        format!(
-            "Failed to change encrypted storage's password for user '{}'",
-            &user
-        )
-    })?;
+    activity::ensure_idle(&user)?;
+    encryption::change_password(&user, &old_password, &new_password)?;
 
-    let encryption_disabled_file_path = format!(
-        "{}/{}/.{}/{}",
-        &libqinit::MAIN_PART_MOUNTPOINT,
-        &libqinit::SYSTEM_HOME_DIR,
-        &user,
-        &storage_encryption::DISABLED_MODE_FILE
-    );
-    if new_password != storage_encryption::DISABLED_MODE_PASSWORD
-        && fs::exists(&encryption_disabled_file_path)?
-    {
-        fs::remove_file(&encryption_disabled_file_path)?;
+    // The master key does not change along with the password: escrowing once is enough
+    if new_password != storage_encryption::DISABLED_MODE_PASSWORD && !escrow::is_escrowed(&user) {
+        if let Err(e) = escrow::escrow(&user, &encrypted_storage_path(&user), &new_password) {
+            error!("Failed to escrow key of user '{}': {}", &user, e);
+        }
     }
 
     Ok(())
 }
 
-pub fn disable_encryption(user: &str, password: &str) -> Result<()> {
-    change_encryption_password(
+/// Sets a new password for a user who forgot theirs, without losing their encrypted data
+pub fn reset_password(
+    user: &str,
+    private_key_path: &str,
+    passphrase: &str,
+    new_password: &str,
+) -> Result<()> {
+    info!("Resetting password of user '{}' using escrowed key", &user);
+    activity::ensure_idle(&user)?;
+    escrow::reset_encryption_password(
         &user,
-        &password,
-        &storage_encryption::DISABLED_MODE_PASSWORD,
+        &encrypted_storage_path(&user),
+        &private_key_path,
+        &passphrase,
+        &new_password,
     )?;
-    fs::File::create(&format!(
-        "{}/{}/.{}/{}",
-        &libqinit::MAIN_PART_MOUNTPOINT,
-        &libqinit::SYSTEM_HOME_DIR,
-        &user,
-        &storage_encryption::DISABLED_MODE_FILE,
-    ))
-    .with_context(|| {
-        format!(
-            "Failed to create file disabling encryption for user '{}'",
-            &user
-        )
-    })?;
+
+    set_recovered_user_password(&user, &new_password)
+}
+
+pub fn encryption_config(user: &str) -> Result<gocryptfs::Config> {
+    gocryptfs::read_config(&encrypted_storage_path(&user))
+}
+
+/// Recovery code for a user's encrypted storage: it is the storage's master key, so it stays valid across
+/// password changes
+pub fn recovery_code(user: &str, password: &str) -> Result<String> {
+    let master_key = gocryptfs::dump_master_key(&encrypted_storage_path(&user), &password)?;
+
+    Ok(gocryptfs::format_master_key(&master_key))
+}
+
+/// Sets a new password for a user who forgot theirs, using the recovery code they kept
+pub fn recover_password(user: &str, recovery_code: &str, new_password: &str) -> Result<()> {
+    info!(
+        "Recovering password of user '{}' using recovery code",
+        &user
+    );
+    activity::ensure_idle(&user)?;
+    let master_key =
+        gocryptfs::parse_master_key(&recovery_code).with_context(|| "Invalid recovery code")?;
+    // gocryptfs re-wraps any key it is given: writing anything before this check would lock the user out
+    rekey::verify_master_key(&user, &master_key)?;
+
+    let path = encrypted_storage_path(&user);
+    gocryptfs::remove_config_backup(&path)?;
+    gocryptfs::set_password_with_master_key(&path, &master_key, &new_password)?;
+    gocryptfs::remove_config_backup(&path)?;
+    if gocryptfs::dump_master_key(&path, &new_password)? != master_key {
+        return Err(anyhow::anyhow!(
+            "Encrypted storage does not unlock with the new password"
+        ));
+    }
+
+    set_recovered_user_password(&user, &new_password)
+}
+
+/// Protects a user's unencrypted data with a new password, moving it into a new encrypted volume
+pub fn encrypt_home(
+    user: &str,
+    new_password: &str,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    rekey::encrypt_plaintext_home(&user, &new_password, progress)?;
+
+    // Escrowed keys of the previous volume are useless, if there were any
+    escrow::remove(&user)?;
+    if let Err(e) = escrow::escrow(&user, &encrypted_storage_path(&user), &new_password) {
+        error!("Failed to escrow key of user '{}': {}", &user, e);
+    }
+
+    set_recovered_user_password(&user, &new_password)
+}
+
+/// Reuse is not refused here: users who forgot their password cannot know which ones they used before. Their
+/// PIN goes away with the old password, as whoever set it may be who the reset is meant to keep out.
+fn set_recovered_user_password(user: &str, new_password: &str) -> Result<()> {
+    pin::remove(&user).with_context(|| "Failed to remove user's PIN")?;
+    change_user_password_chroot_command(&OVERLAY_MOUNTPOINT, &user, None, &new_password, false)
+        .with_context(|| "Failed to set user's new password")?;
+    if let Err(e) = lockout::reset(&user) {
+        error!("Failed to reset failed authentication attempts: {}", &e);
+    }
+    record_password(&user, &new_password);
 
     Ok(())
 }
 
+pub fn disable_encryption(user: &str, password: &str) -> Result<()> {
+    activity::ensure_idle(&user)?;
+    encryption::disable(&user, &password)
+}
+
+pub fn encryption_status(user: &str) -> Result<EncryptionStatus> {
+    encryption::status(&user)
+}
+
 fn change_user_password_chroot_command(
     chroot_path: &str,
     user: &str,
@@ -134,27 +205,24 @@ fn change_user_password_chroot_command(
     Ok(())
 }
 
-fn create_user_chroot_command(chroot_path: &str, username: &str, admin: bool) -> Result<()> {
+fn create_user_chroot_command(
+    chroot_path: &str,
+    username: &str,
+    admin: bool,
+    shell: Option<&str>,
+) -> Result<()> {
     let useradd_path = "/usr/sbin/useradd";
+    let config = Config::read()?;
 
+    let mut args = vec![chroot_path, useradd_path, "-M"];
     if admin {
-        system::run_command(
-            "/usr/sbin/chroot",
-            &[
-                &chroot_path,
-                &useradd_path,
-                "-M",
-                "-G",
-                &ADMIN_GROUP,
-                &username,
-            ],
-        )?;
-    } else {
-        system::run_command(
-            "/usr/sbin/chroot",
-            &[&chroot_path, &useradd_path, "-M", &username],
-        )?;
+        args.extend(["-G", config.primary_admin_group()]);
     }
+    if let Some(shell) = shell {
+        args.extend(["-s", shell]);
+    }
+    args.push(username);
+    system::run_command("/usr/sbin/chroot", &args)?;
 
     Ok(())
 }
@@ -182,6 +250,41 @@ pub fn change_user_password(
         handle_rootfs = false;
     }
 
+    // Only verifying the current password is harmless while the user is logged in
+    if let Err(e) = lockout::check(&user).and_then(|_| {
+        if new_password.is_some() {
+            activity::ensure_idle(&user)
+        } else {
+            Ok(())
+        }
+    }) {
+        if handle_rootfs {
+            rootfs::tear_down()?;
+        }
+        return Err(e);
+    }
+
+    // Checked once the current password is verified, so that the history cannot be probed without it
+    if let Some(password) = new_password {
+        let reused = password == old_password
+            || password_history::is_reused(&user, &password).unwrap_or_else(|e| {
+                error!(
+                    "Failed to check password history of user '{}': {}",
+                    &user, e
+                );
+                false
+            });
+        if reused {
+            if handle_rootfs {
+                rootfs::tear_down()?;
+            }
+            change_user_password(pubkey, &user, &old_password, None)?;
+            return Err(anyhow::anyhow!(
+                "This password was used recently: please choose a different one"
+            ));
+        }
+    }
+
     let mut shadow_backup: Option<String> = None;
     let mut shadow_file_path: Option<String> = None;
     let new_password_string;
@@ -200,7 +303,6 @@ pub fn change_user_password(
     }
 
     let temporary_password = system::generate_random_string(128)?;
-    info!("Temporary password is '{}'", &temporary_password);
 
     let mut do_error = false;
     info!("Setting temporary password for verification");
@@ -213,7 +315,13 @@ pub fn change_user_password(
     ) {
         do_error = true;
         error!("{}", &e);
+        if let Err(e) = lockout::record_failure(&user) {
+            error!("Failed to record failed authentication attempt: {}", &e);
+        }
     } else {
+        if let Err(e) = lockout::reset(&user) {
+            error!("Failed to reset failed authentication attempts: {}", &e);
+        }
         info!("Setting new requested password");
         if let Err(e) = change_user_password_chroot_command(
             &OVERLAY_MOUNTPOINT,
@@ -244,24 +352,20 @@ pub fn change_user_password(
             &user
         ));
     }
+    if let Some(password) = new_password {
+        record_password(&user, &password);
+    }
 
     Ok(())
 }
 
-fn initialize_encrypted_storage(path: &str, password: &str) -> Result<()> {
-    system::run_command(
-        "/bin/sh",
-        &[
-            "-c",
-            &format!(
-                "printf '{}\n{}' | {} -init {}",
-                &password, &password, &GOCRYPTFS_BINARY, &path
-            ),
-        ],
-    )
-    .with_context(|| format!("Failed to initialize encrypted storage at path '{}'", &path))?;
-
-    Ok(())
+fn record_password(user: &str, password: &str) {
+    if let Err(e) = password_history::record(&user, &password) {
+        error!(
+            "Failed to record password of user '{}' in history: {}",
+            &user, e
+        );
+    }
 }
 
 pub fn set_default_user(user: &str, boot_config: Arc<Mutex<BootConfig>>) -> Result<()> {
@@ -289,18 +393,28 @@ pub fn remove_from_group(user: &str, group: &str) -> Result<()> {
     ])?)
 }
 
+fn admin_groups_of(user: &str) -> Result<Vec<String>> {
+    let mut admin_groups = Vec::new();
+    for group in Config::read()?.admin_groups {
+        // Configured administrator groups may not exist (yet)
+        if groups::get(&group).is_ok() && groups::is_member(&user, &group)? {
+            admin_groups.push(group);
+        }
+    }
+
+    Ok(admin_groups)
+}
+
 pub fn is_admin(user: &str) -> bool {
-    if let Err(_) = run_chroot_command(&[
-        "/bin/sh",
-        "-c",
-        &format!(
-            "/usr/sbin/groups '{}' | /usr/sbin/grep -q '{}'",
-            &user, &ADMIN_GROUP
-        ),
-    ]) {
-        return false;
-    } else {
-        return true;
+    match admin_groups_of(&user) {
+        Ok(admin_groups) => !admin_groups.is_empty(),
+        Err(e) => {
+            error!(
+                "Failed to check administrator status of user '{}': {}",
+                &user, e
+            );
+            false
+        }
     }
 }
 
@@ -309,7 +423,12 @@ pub fn admin_login_verify(username: &str, password: &str) -> AdminLoginStatus {
         return AdminLoginStatus::NotAdmin;
     }
 
-    if let Err(_) = change_user_password(None, &username, &password, None) {
+    if let Err(e) = change_user_password(None, &username, &password, None) {
+        if let Some(locked_out) = e.downcast_ref::<LockedOut>() {
+            return AdminLoginStatus::LockedOut {
+                retry_after: locked_out.retry_after,
+            };
+        }
         return AdminLoginStatus::Failure;
     } else {
         return AdminLoginStatus::Success;
@@ -330,40 +449,72 @@ pub fn change_admin_status(user: &str, make_admin: bool) -> Result<()> {
     }
 
     if make_admin {
-        add_to_group(&user, &ADMIN_GROUP)?;
+        if restrictions::is_restricted(&user) {
+            return Err(anyhow::anyhow!(
+                "User '{}' is restricted: lift their restrictions first",
+                &user
+            ));
+        }
+        add_to_group(&user, &Config::read()?.primary_admin_group())?;
     } else {
-        remove_from_group(&user, &ADMIN_GROUP)?;
+        for group in admin_groups_of(&user)? {
+            if groups::is_primary_group(&user, &group)? {
+                leave_primary_admin_group(&user, &group)?;
+            }
+            if groups::get(&group)?
+                .members
+                .iter()
+                .any(|member| member == user)
+            {
+                remove_from_group(&user, &group)?;
+            }
+        }
     }
 
     Ok(())
 }
 
-pub fn count_admin_users() -> Result<usize> {
-    // ChatGPT did help
-    let contents = fs::read_to_string(&format!("{}/etc/group", &OVERLAY_MOUNTPOINT))
-        .with_context(|| "Failed to read groups file")?;
-
-    for line in contents.lines() {
-        if line.starts_with(&format!("{}:", &ADMIN_GROUP)) {
-            let fields: Vec<&str> = line.split(':').collect();
-
-            let member_count = fields
-                .get(3)
-                .map(|members| {
-                    if members.is_empty() {
-                        0
-                    } else {
-                        members.split(',').count()
-                    }
-                })
-                .unwrap_or(0);
-
-            info!("Found {} administrator user(s)", &member_count);
-            return Ok(member_count);
+/// Moves the user to their own group as primary group, which is what useradd gives them by default
+fn leave_primary_admin_group(user: &str, group: &str) -> Result<()> {
+    if group == user || groups::get(&user).is_err() {
+        return Err(anyhow::anyhow!(
+            "Administrator group '{}' is the primary group of user '{}', and they have no group of their own to use instead",
+            &group,
+            &user
+        ));
+    }
+
+    info!(
+        "Changing primary group of user '{}' from '{}' to '{}'",
+        &user, &group, &user
+    );
+    run_chroot_command(&["/usr/sbin/usermod", "-g", &user, &user])
+        .with_context(|| format!("Failed to change primary group of user '{}'", &user))?;
+
+    Ok(())
+}
+
+pub fn admin_users() -> Result<Vec<String>> {
+    let admin_groups = Config::read()?.admin_groups;
+    let mut admin_users: Vec<String> = Vec::new();
+    for group in groups::list()? {
+        if admin_groups.contains(&group.name) {
+            for member in groups::members(&group)? {
+                if !admin_users.contains(&member) {
+                    admin_users.push(member);
+                }
+            }
         }
     }
 
-    Ok(0)
+    Ok(admin_users)
+}
+
+pub fn count_admin_users() -> Result<usize> {
+    let member_count = admin_users()?.len();
+    info!("Found {} administrator user(s)", &member_count);
+
+    Ok(member_count)
 }
 
 pub fn set_default(user: Option<&str>, boot_config: Arc<Mutex<BootConfig>>) {
@@ -374,52 +525,72 @@ pub fn set_default(user: Option<&str>, boot_config: Arc<Mutex<BootConfig>>) {
     }
 }
 
-pub fn create(
+/// Creates the UNIX user only, without any storage
+pub(crate) fn create_account(
     username: &str,
     password: &str,
     admin: bool,
-    make_default: bool,
-    boot_config: Arc<Mutex<BootConfig>>,
+    shell: Option<&str>,
 ) -> Result<()> {
     if username.contains(".") || username.contains("/") {
         return Err(anyhow::anyhow!("Username contains forbidden characters"));
     }
 
-    create_user_chroot_command(&OVERLAY_MOUNTPOINT, &username, admin)
+    create_user_chroot_command(&OVERLAY_MOUNTPOINT, &username, admin, shell)
         .with_context(|| "Failed to create UNIX user in chroot")?;
     change_user_password_chroot_command(&OVERLAY_MOUNTPOINT, &username, None, &password, false)
         .with_context(|| "Failed to set new UNIX user's password")?;
+    record_password(&username, &password);
 
-    let home_dir_path = format!("{}/{}/{}", &OVERLAY_MOUNTPOINT, &SYSTEM_HOME_DIR, &username);
-    let encrypted_home_dir_path = format!(
-        "{}/{}/.{}",
-        &OVERLAY_MOUNTPOINT, &SYSTEM_HOME_DIR, &username
-    );
+    Ok(())
+}
+
+/// Settings of the profile, when given, take precedence over `admin` (which it can only grant) and `encrypt`.
+/// Storage created without encryption is in disabled mode, and can be encrypted later on.
+pub fn create(
+    username: &str,
+    password: &str,
+    admin: bool,
+    make_default: bool,
+    encrypt: bool,
+    profile: Option<&Profile>,
+    boot_config: Arc<Mutex<BootConfig>>,
+) -> Result<()> {
+    let admin = admin || profile.and_then(|profile| profile.admin).unwrap_or(false);
+    let encrypt = profile
+        .and_then(|profile| profile.encryption)
+        .unwrap_or(encrypt);
+    create_account(
+        &username,
+        &password,
+        admin,
+        profile.and_then(|profile| profile.shell.as_deref()),
+    )?;
+
+    let encrypted_home_dir_path = encrypted_storage_path(&username);
     fs::create_dir_all(&encrypted_home_dir_path)?;
-    fs::create_dir_all(&home_dir_path)?;
-
-    initialize_encrypted_storage(&encrypted_home_dir_path, &password)?;
-    storage_encryption::mount_storage(&username, &password)?;
-
-    rootfs::run_chroot_command(&[
-        "/bin/sh",
-        "-c",
-        &format!(
-            "/bin/cp -r /etc/skel/.* /{}/{}",
-            &SYSTEM_HOME_DIR, &username
-        ),
-    ])
+    fs::create_dir_all(&home_dir_path(&username))?;
+
+    encryption::init(&username, &password)?;
+    if let Err(e) = escrow::escrow(&username, &encrypted_home_dir_path, &password) {
+        error!("Failed to escrow key of user '{}': {}", &username, e);
+    }
+    encryption::mount(&username, &password)?;
+
+    skeleton::populate(
+        &username,
+        profile.and_then(|profile| profile.skeleton.as_deref()),
+    )
     .with_context(|| "Failed to copy skeleton directory file(s) to new user's home directory")?;
-    rootfs::run_chroot_command(&[
-        "/usr/sbin/chown",
-        "-R",
-        &format!("{}:{}", &username, &username),
-        &format!("/{}/{}", &SYSTEM_HOME_DIR, &username),
-    ])
-    .with_context(|| "Failed to set filesystem permissions")?;
 
-    storage_encryption::unmount_storage(&username)
-        .with_context(|| "Failed to unmount encrypted storage")?;
+    encryption::unmount(&username).with_context(|| "Failed to unmount encrypted storage")?;
+    if !encrypt {
+        encryption::disable(&username, &password)?;
+    }
+
+    if let Some(profile) = profile {
+        apply_profile(&username, &profile)?;
+    }
 
     if make_default {
         set_default_user(&username, boot_config).with_context(|| "Failed to set default user")?
@@ -428,19 +599,88 @@ pub fn create(
     Ok(())
 }
 
-pub fn delete(user: &str) -> Result<()> {
-    if !user.is_empty() {
-        let home_dir_path = format!("{}/{}/{}", &OVERLAY_MOUNTPOINT, &SYSTEM_HOME_DIR, &user);
-        let encrypted_home_dir_path =
-            format!("{}/{}/.{}", &OVERLAY_MOUNTPOINT, &SYSTEM_HOME_DIR, &user);
+fn apply_profile(user: &str, profile: &Profile) -> Result<()> {
+    info!("Applying profile '{}' to user '{}'", &profile.name, &user);
+    for group in &profile.groups {
+        // Profiles are shared between devices, which may not all have the same groups
+        if groups::get(&group).is_err() {
+            warn!(
+                "Group '{}' of profile '{}' does not exist",
+                &group, &profile.name
+            );
+            continue;
+        }
+        add_to_group(&user, &group)
+            .with_context(|| format!("Failed to add user to group '{}'", &group))?;
+    }
+    if let Some(quota_bytes) = profile.quota_bytes {
+        usage::set_quota(&user, Some(quota_bytes)).with_context(|| "Failed to set quota")?;
+    }
+
+    Ok(())
+}
 
-        system::rm_dir_all(&home_dir_path)
+/// Destroys the keys protecting a user's storage before deleting the user, so that whatever remains of the
+/// ciphertext on flash cannot be decrypted. Returns what was wiped.
+pub fn secure_delete(user: &str, boot_config: Arc<Mutex<BootConfig>>) -> Result<Vec<String>> {
+    if user.is_empty() {
+        return Err(anyhow::anyhow!("No username provided"));
+    }
+    ensure_not_last_admin(&user)?;
+    activity::ensure_idle(&user)?;
+
+    info!("Securely deleting user '{}'", &user);
+    let mut wiped =
+        encryption::destroy_keys(&user).with_context(|| "Failed to destroy encryption keys")?;
+    if escrow::destroy(&user)? {
+        wiped.push("Escrowed key".to_string());
+    }
+    let storage_path = encrypted_storage_path(&user);
+    let storage_existed = fs::exists(&storage_path)?;
+    delete(&user, boot_config)?;
+    if storage_existed && !fs::exists(&storage_path)? {
+        wiped.push("Storage directory".to_string());
+    }
+    if wipe::discard_unused_blocks(&libqinit::MAIN_PART_MOUNTPOINT) {
+        wiped.push("Unused blocks of the data partition".to_string());
+    }
+
+    Ok(wiped)
+}
+
+/// Nobody could manage the system anymore without an administrator
+fn ensure_not_last_admin(user: &str) -> Result<()> {
+    if is_admin(&user) && count_admin_users()? <= 1 {
+        return Err(anyhow::anyhow!(
+            "User '{}' is the last administrator: at least one administrator required",
+            &user
+        ));
+    }
+
+    Ok(())
+}
+
+/// Also forgets everything Core Settings and the boot configuration keep about the user, so that nothing applies
+/// to another user created later on with the same name
+pub fn delete(user: &str, boot_config: Arc<Mutex<BootConfig>>) -> Result<()> {
+    if !user.is_empty() {
+        ensure_not_last_admin(&user)?;
+        activity::ensure_idle(&user)?;
+        system::rm_dir_all(&home_dir_path(&user))
             .with_context(|| "Failed to remove user's home directory")?;
-        system::rm_dir_all(&encrypted_home_dir_path)
+        system::rm_dir_all(&encrypted_storage_path(&user))
             .with_context(|| "Failed to remove user's encrypted home directory")?;
 
         run_chroot_command(&["/usr/sbin/userdel", "-f", "-r", &user])
             .with_context(|| "Failed to remove UNIX user from overlay filesystem")?;
+        escrow::remove(&user)?;
+        usage::remove(&user)?;
+        restrictions::remove(&user)?;
+        pin::remove(&user)?;
+        password_history::remove(&user)?;
+        lockout::reset(&user)?;
+        autologin::remove(&user, boot_config.clone());
+        loginscreen::remove(&user, boot_config);
     } else {
         return Err(anyhow::anyhow!("No username provided"));
     }
@@ -0,0 +1,53 @@
+use anyhow::{Context, Result};
+use libqinit::system;
+use log::{info, warn};
+use std::{fs, io::Write, path::Path};
+
+pub const FSTRIM_BINARY: &str = "/sbin/fstrim";
+const OVERWRITE_BLOCK_SIZE: usize = 4096;
+
+/// Overwrites a file with random data before removing it. On flash storage, wear leveling may keep old copies
+/// of the blocks around: this is only meant for small key material, which makes the rest of the data useless.
+pub fn overwrite_and_remove(path: &str) -> Result<()> {
+    let length = fs::metadata(&path)
+        .with_context(|| format!("Failed to read metadata of '{}'", &path))?
+        .len() as usize;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open '{}'", &path))?;
+    let mut block = vec![0; OVERWRITE_BLOCK_SIZE];
+    let mut written = 0;
+    // Rounded up to whole blocks, so that the tail of the last one is covered as well
+    while written < length.max(1) {
+        openssl::rand::rand_bytes(&mut block)?;
+        file.write_all(&block)?;
+        written += block.len();
+    }
+    file.sync_all()
+        .with_context(|| format!("Failed to overwrite '{}'", &path))?;
+    drop(file);
+
+    fs::remove_file(&path).with_context(|| format!("Failed to remove '{}'", &path))?;
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::File::open(&parent)?.sync_all()?;
+    }
+    info!("Overwrote and removed '{}'", &path);
+
+    Ok(())
+}
+
+/// Lets the storage device erase blocks of removed files. Not all devices and filesystems support it.
+pub fn discard_unused_blocks(mountpoint: &str) -> bool {
+    match system::run_command(&FSTRIM_BINARY, &[&mountpoint]) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(
+                "Failed to discard unused blocks of '{}': {}",
+                &mountpoint, e
+            );
+            false
+        }
+    }
+}